@@ -3,8 +3,10 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use market::msg::{ListingResponse, ExecuteMsg, InstantiateMsg, QueryMsg};
-use market::state::State;
+use market::msg::{
+    ConfigResponse, ExecuteMsg, InstantiateMsg, ListingResponse, MigrateMsg, QueryMsg,
+};
+use market::state::Config;
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -15,6 +17,8 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
-    export_schema(&schema_for!(State), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
+    export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(ListingResponse), &out_dir);
+    export_schema(&schema_for!(ConfigResponse), &out_dir);
 }
@@ -1,24 +1,48 @@
+use std::collections::BTreeMap;
+
 use cosmwasm_std::{
-    entry_point, to_binary, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo,
-    Querier, QueryRequest, Response, StdResult, Uint128, WasmMsg, WasmQuery,
+    entry_point, from_binary, to_binary, Addr, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut,
+    Env, MessageInfo, Order, QueryRequest, Response, StdError, StdResult, Storage, Timestamp,
+    Uint128, WasmMsg, WasmQuery,
 };
 use cw2::set_contract_version;
+use cw20::{Cw20Coin, Cw20ReceiveMsg};
 use cw721::{
-    Approval, ApprovedForAllResponse, Cw721ExecuteMsg, Cw721QueryMsg, Expiration, OwnerOfResponse,
+    Approval, ApprovedForAllResponse, Cw721ExecuteMsg, Cw721QueryMsg, Cw721ReceiveMsg, Expiration,
+    OwnerOfResponse,
 };
-use cw_storage_plus::Bound;
+use cw_storage_plus::{Bound, PrimaryKey, U128Key, U64Key};
+use sha2::{Digest, Sha256};
 
 use crate::error::ContractError;
 use crate::msg::{
-    AuctionListingResponse, ExecuteMsg, HighestBidderResponse, InstantiateMsg, ListingResponse,
-    ListingsResponse, QueryMsg,
+    AuctionListingResponse, AuctionListingsResponse, BidsResponse, BundleResponse, BundlesResponse,
+    CollectionOfferResponse, CollectionOffersResponse, ConfigResponse, CurrentPriceResponse,
+    Cw2981ExtensionMsg, Cw2981QueryMsg, ExecuteMsg, HighestBidderResponse, InstantiateMsg,
+    ListingKey, ListingResponse, ListingsResponse, MigrateMsg, OffersResponse, QueryMsg,
+    ReceiveMsg, ReceiveNftMsg, RoyaltiesInfoResponse, RoyaltyResponse, SealedAuctionResponse,
+    SealedBidsResponse, SellItem, TradeResponse, TradesResponse,
+};
+use crate::state::{
+    auction_listings, collection_offers, listings, offers, AuctionExtension, AuctionNft,
+    BidIncrement, BidRecord, Bidder, Bundle, BundleItem, BundleKind, CancelPenalty,
+    CollectionOffer, Config, DutchAuction, Offer, Price, ReservePrice, Royalty, SealedAuction,
+    SealedBid, SealedBidPricing, Trade, AUCTION_COUNT, BIDS, BID_COUNT, BUNDLED_NFTS, BUNDLES,
+    BUNDLE_BIDS, BUNDLE_COUNT, CONFIG, HIGHEST_BIDDER, LEGACY_AUCTION_LIST, LEGACY_HIGHEST_BIDDER,
+    LEGACY_LIST, NFT, ROYALTIES, SEALED_AUCTIONS, SEALED_BIDS, TRADES, TRADE_COUNT,
 };
-use crate::state::{AuctionNft, Bidder, AUCTION_LIST, HIGHEST_BIDDER, LIST, NFT};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "crates.io:{{marketplace}}";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// 10_000 basis points make up 100%
+const MAX_BPS: u64 = 10_000;
+
+// settings for pagination
+const MAX_LIMIT: u32 = 30;
+const DEFAULT_LIMIT: u32 = 10;
+
 fn get_token_owner(
     deps: Deps,
     token_id: String,
@@ -50,16 +74,306 @@ fn get_token_approval(
         }))?;
     Ok(res.operators)
 }
+// Whether the marketplace currently holds an unexpired approval among the owner's approvals
+fn marketplace_approved(env: &Env, approvals: &[Approval]) -> bool {
+    approvals.iter().any(|approval| {
+        approval.spender == env.contract.address && !approval.expires.is_expired(&env.block)
+    })
+}
+// Whether the marketplace is approved at least until a listing expires. An approval
+// expiring in other units than the listing can't be compared and doesn't count.
+fn marketplace_approved_until(env: &Env, approvals: &[Approval], expiration: &Expiration) -> bool {
+    approvals.iter().any(|approval| {
+        approval.spender == env.contract.address
+            && !approval.expires.is_expired(&env.block)
+            && approval.expires >= *expiration
+    })
+}
+fn assert_marketplace_approved(
+    deps: Deps,
+    env: &Env,
+    contract_address: String,
+    owner: String,
+    expiration: &Expiration,
+) -> Result<(), ContractError> {
+    let approvals = get_token_approval(deps, contract_address, owner)?;
+    if !marketplace_approved_until(env, &approvals, expiration) {
+        return Err(ContractError::Unapproved {});
+    }
+    Ok(())
+}
+// Whether the marketplace can still transfer a token it doesn't hold out of owner's wallet
+fn can_transfer(
+    deps: Deps,
+    env: &Env,
+    contract_address: &str,
+    token_id: &str,
+    owner: &str,
+) -> bool {
+    get_token_owner(deps, token_id.to_string(), contract_address.to_string())
+        .map_or(false, |token_owner| token_owner == owner)
+        && get_token_approval(deps, contract_address.to_string(), owner.to_string())
+            .map_or(false, |approvals| marketplace_approved(env, &approvals))
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    _info: MessageInfo,
-    _msg: InstantiateMsg,
-) -> StdResult<Response> {
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    Ok(Response::default())
+    // the instantiator owns the marketplace unless another owner is given
+    let owner = match msg.owner {
+        Some(owner) => deps.api.addr_validate(&owner)?,
+        None => info.sender,
+    };
+    // fees go to the owner unless another collector is given
+    let fee_collector = match msg.fee_collector {
+        Some(fee_collector) => deps.api.addr_validate(&fee_collector)?,
+        None => owner.clone(),
+    };
+    if msg.fee_bps > MAX_BPS {
+        return Err(ContractError::InvalidFee {});
+    }
+    let cancel_penalty = msg.cancel_penalty.unwrap_or_default();
+    assert_cancel_penalty(&cancel_penalty)?;
+    let config = Config {
+        owner,
+        accepted_denoms: msg.accepted_denoms,
+        accepted_cw20s: msg.accepted_cw20s,
+        fee_bps: msg.fee_bps,
+        fee_collector,
+        auction_extension: msg.auction_extension.unwrap_or_default(),
+        cancel_penalty,
+    };
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "instantiate")
+        .add_attribute("owner", config.owner)
+        .add_attribute("fee_bps", config.fee_bps.to_string()))
+}
+
+// A penalty can't exceed the bid it is taken from
+fn assert_cancel_penalty(cancel_penalty: &CancelPenalty) -> Result<(), ContractError> {
+    match cancel_penalty {
+        CancelPenalty::BasisPoints(bps) if *bps > MAX_BPS => Err(ContractError::InvalidFee {}),
+        _ => Ok(()),
+    }
+}
+
+fn assert_accepted_denom(deps: Deps, price: &Price) -> Result<(), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let accepted = match price {
+        Price::Native(_) => &config.accepted_denoms,
+        Price::Cw20(_) => &config.accepted_cw20s,
+    };
+    if !accepted.iter().any(|accepted| accepted == price.denom()) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    Ok(())
+}
+
+// Native payments are escrowed from the funds sent along, which must be exactly one coin.
+fn native_payment(info: &MessageInfo) -> Result<Price, ContractError> {
+    if info.funds.len() != 1 {
+        return Err(ContractError::InvalidFunds {});
+    }
+    Ok(Price::Native(info.funds[0].clone()))
+}
+
+// Looks up the royalty owed on a sale, first through the collection's cw2981 RoyaltyInfo
+// query and then through the royalty registry kept by the marketplace.
+fn get_royalty(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+    sale_price: &Price,
+) -> StdResult<Option<(String, Price)>> {
+    let res: StdResult<RoyaltiesInfoResponse> =
+        deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: contract_address.clone(),
+            msg: to_binary(&Cw2981QueryMsg::Extension {
+                msg: Cw2981ExtensionMsg::RoyaltyInfo {
+                    token_id,
+                    sale_price: sale_price.amount(),
+                },
+            })?,
+        }));
+    let royalty = match res {
+        // a recipient that can't be paid would make every sale of the collection fail
+        Ok(res) => deps
+            .api
+            .addr_validate(&res.address)
+            .ok()
+            .map(|recipient| (recipient.to_string(), res.royalty_amount)),
+        // collections that don't implement cw2981 fall back to the registry
+        Err(_) => ROYALTIES
+            .may_load(deps.storage, contract_address)?
+            .map(|royalty| {
+                (
+                    royalty.recipient.to_string(),
+                    sale_price.amount().multiply_ratio(royalty.bps, MAX_BPS),
+                )
+            }),
+    };
+    // a royalty can't take more than the sale price
+    Ok(royalty
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(recipient, amount)| {
+            (
+                recipient,
+                sale_price.with_amount(amount.min(sale_price.amount())),
+            )
+        }))
+}
+
+struct SalePayouts {
+    messages: Vec<CosmosMsg>,
+    /// The recipients and amounts paid by messages
+    transfers: Vec<(String, Price)>,
+    fee: Price,
+    royalty: Price,
+    royalty_recipient: Option<String>,
+    seller_amount: Price,
+}
+
+impl SalePayouts {
+    // Adds the payout messages to res along with attributes reporting the split
+    fn add_to(self, res: Response) -> Response {
+        res.add_messages(self.messages)
+            .add_attribute("Fee", self.fee.to_string())
+            .add_attribute("Royalty", self.royalty.to_string())
+            .add_attribute(
+                "RoyaltyRecipient",
+                self.royalty_recipient.unwrap_or_default(),
+            )
+            .add_attribute("SellerAmount", self.seller_amount.to_string())
+    }
+}
+
+// Splits a sale's payment between the fee collector, the creator and the seller.
+fn sale_payouts(
+    deps: Deps,
+    payment: Price,
+    seller: String,
+    token_id: String,
+    contract_address: String,
+) -> Result<SalePayouts, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let fee = payment.with_amount(payment.amount().multiply_ratio(config.fee_bps, MAX_BPS));
+    let remaining = payment.amount() - fee.amount();
+    let (royalty_recipient, royalty) =
+        match get_royalty(deps, token_id, contract_address, &payment)? {
+            // the royalty is paid out of what is left after the fee
+            Some((recipient, royalty)) if !remaining.is_zero() => (
+                Some(recipient),
+                royalty.with_amount(royalty.amount().min(remaining)),
+            ),
+            _ => (None, payment.with_amount(Uint128::zero())),
+        };
+    let seller_amount = payment.with_amount(remaining - royalty.amount());
+    let mut transfers = vec![];
+    // transfers of zero coins fail, so skip empty shares
+    if !fee.amount().is_zero() {
+        transfers.push((config.fee_collector.to_string(), fee.clone()));
+    }
+    if let Some(recipient) = &royalty_recipient {
+        transfers.push((recipient.clone(), royalty.clone()));
+    }
+    if !seller_amount.amount().is_zero() {
+        transfers.push((seller, seller_amount.clone()));
+    }
+    let messages = transfers
+        .iter()
+        .map(|(recipient, amount)| amount.clone().into_msg(recipient.clone()))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(SalePayouts {
+        messages,
+        transfers,
+        fee,
+        royalty,
+        royalty_recipient,
+        seller_amount,
+    })
+}
+
+// Merges transfers to the same recipient, paying all native coins a recipient is owed in a
+// single BankMsg::Send and each cw20 token in a single transfer.
+fn merge_transfers(transfers: Vec<(String, Price)>) -> StdResult<Vec<CosmosMsg>> {
+    let mut native: BTreeMap<String, BTreeMap<String, Uint128>> = BTreeMap::new();
+    let mut cw20: BTreeMap<(String, String), Uint128> = BTreeMap::new();
+    for (recipient, amount) in transfers {
+        match amount {
+            Price::Native(coin) => {
+                *native
+                    .entry(recipient)
+                    .or_default()
+                    .entry(coin.denom)
+                    .or_default() += coin.amount;
+            }
+            Price::Cw20(coin) => {
+                *cw20.entry((recipient, coin.address)).or_default() += coin.amount;
+            }
+        }
+    }
+    let mut messages = vec![];
+    for (recipient, coins) in native {
+        let amount: Vec<Coin> = coins
+            .into_iter()
+            .filter(|(_, amount)| !amount.is_zero())
+            .map(|(denom, amount)| Coin { denom, amount })
+            .collect();
+        if !amount.is_empty() {
+            messages.push(CosmosMsg::Bank(BankMsg::Send {
+                to_address: recipient,
+                amount,
+            }));
+        }
+    }
+    for ((recipient, address), amount) in cw20 {
+        if !amount.is_zero() {
+            messages.push(Price::Cw20(Cw20Coin { address, amount }).into_msg(recipient)?);
+        }
+    }
+    Ok(messages)
+}
+
+// Prices one item of a batch buy, taking its price out of the buyer's balance in the same
+// asset once every check has passed.
+fn batch_buy_item(
+    deps: Deps,
+    env: &Env,
+    buyer: &str,
+    balances: &mut [Price],
+    item: &ListingKey,
+) -> Result<(NFT, SalePayouts), ContractError> {
+    let (nft, price) = listing_price(
+        deps,
+        env,
+        item.token_id.clone(),
+        item.contract_address.clone(),
+    )?;
+    let balance = balances
+        .iter_mut()
+        .find(|balance| balance.same_asset(&price))
+        .ok_or(ContractError::InvalidDenomination {})?;
+    let remaining = balance
+        .amount()
+        .checked_sub(price.amount())
+        .map_err(|_| ContractError::InvalidFunds {})?;
+    assert_buyable(deps, buyer, &nft)?;
+    let payouts = sale_payouts(
+        deps,
+        price,
+        nft.owner.clone(),
+        nft.token_id.clone(),
+        nft.contract_address.clone(),
+    )?;
+    *balance = balance.with_amount(remaining);
+    Ok((nft, payouts))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -75,6 +389,7 @@ pub fn execute(
             contract_address,
             price,
             expiration,
+            reserved_for,
         } => execute_sell(
             deps,
             env,
@@ -83,8 +398,127 @@ pub fn execute(
             contract_address,
             price,
             expiration,
+            reserved_for,
+        ),
+        ExecuteMsg::BatchSell { items, best_effort } => {
+            execute_batch_sell(deps, env, info, items, best_effort.unwrap_or(false))
+        }
+        ExecuteMsg::BatchDelist { items, best_effort } => {
+            execute_batch_delist(deps, info, items, best_effort.unwrap_or(false))
+        }
+        ExecuteMsg::BatchBuy { items, best_effort } => {
+            if info.funds.is_empty() {
+                return Err(ContractError::InvalidFunds {});
+            }
+            let payment = info.funds.into_iter().map(Price::Native).collect();
+            execute_batch_buy(
+                deps,
+                env,
+                info.sender.to_string(),
+                payment,
+                items,
+                best_effort.unwrap_or(false),
+            )
+        }
+        ExecuteMsg::ProposeTrade {
+            offered,
+            requested,
+            extra_funds,
+            counterparty,
+        } => {
+            let payment = if info.funds.is_empty() {
+                None
+            } else {
+                Some(native_payment(&info)?)
+            };
+            execute_propose_trade(
+                deps,
+                env,
+                info.sender.to_string(),
+                payment,
+                offered,
+                requested,
+                extra_funds,
+                counterparty,
+            )
+        }
+        ExecuteMsg::AcceptTrade { trade_id } => execute_accept_trade(deps, env, info, trade_id),
+        ExecuteMsg::CancelTrade { trade_id } => execute_cancel_trade(deps, info, trade_id),
+        ExecuteMsg::SellBundle {
+            nfts,
+            price,
+            expiration,
+        } => execute_list_bundle(
+            deps,
+            env,
+            info,
+            nfts,
+            price,
+            BundleKind::FixedPrice,
+            expiration,
+        ),
+        ExecuteMsg::AuctionBundle {
+            nfts,
+            starting_price,
+            min_bid_increment,
+            expiration,
+        } => execute_list_bundle(
+            deps,
+            env,
+            info,
+            nfts,
+            starting_price,
+            BundleKind::Auction { min_bid_increment },
+            expiration,
+        ),
+        ExecuteMsg::BuyBundle { bundle_id } => {
+            let payment = native_payment(&info)?;
+            execute_buy_bundle(deps, env, info.sender.to_string(), payment, bundle_id)
+        }
+        ExecuteMsg::BidBundle { bundle_id } => {
+            let bid = native_payment(&info)?;
+            execute_bid_bundle(deps, env, info.sender.to_string(), bid, bundle_id)
+        }
+        ExecuteMsg::ClaimBundle { bundle_id } => execute_claim_bundle(deps, env, info, bundle_id),
+        ExecuteMsg::DelistBundle { bundle_id } => execute_delist_bundle(deps, info, bundle_id),
+        ExecuteMsg::UpdatePrice {
+            collection,
+            token_id,
+            price,
+            expiration,
+        } => execute_update_price(deps, env, info, collection, token_id, price, expiration),
+        ExecuteMsg::DutchAuction {
+            token_id,
+            contract_address,
+            start_price,
+            end_price,
+            start_time,
+            end_time,
+        } => execute_dutch_auction(
+            deps,
+            env,
+            info,
+            token_id,
+            contract_address,
+            start_price,
+            end_price,
+            start_time,
+            end_time,
         ),
-        ExecuteMsg::Buy { token_id } => execute_buy(deps, env, info, token_id),
+        ExecuteMsg::Buy {
+            token_id,
+            contract_address,
+        } => {
+            let payment = native_payment(&info)?;
+            execute_buy(
+                deps,
+                env,
+                info.sender.to_string(),
+                payment,
+                token_id,
+                contract_address,
+            )
+        }
         ExecuteMsg::Delist {
             token_id,
             contract_address,
@@ -93,8 +527,105 @@ pub fn execute(
             token_id,
             contract_address,
             starting_price,
+            min_bid_increment,
             expiration,
+            reserve_price,
+            buy_now_price,
         } => execute_auction(
+            deps,
+            env,
+            info,
+            token_id,
+            contract_address,
+            starting_price,
+            min_bid_increment,
+            expiration,
+            reserve_price,
+            buy_now_price,
+        ),
+        ExecuteMsg::Bid {
+            token_id,
+            contract_address,
+        } => {
+            let bid = native_payment(&info)?;
+            execute_bid(
+                deps,
+                env,
+                info.sender.to_string(),
+                bid,
+                token_id,
+                contract_address,
+            )
+        }
+        ExecuteMsg::SealedAuction {
+            token_id,
+            contract_address,
+            min_price,
+            commit_end,
+            reveal_end,
+            pricing,
+        } => execute_sealed_auction(
+            deps,
+            env,
+            info,
+            token_id,
+            contract_address,
+            min_price,
+            commit_end,
+            reveal_end,
+            pricing,
+        ),
+        ExecuteMsg::CommitBid {
+            token_id,
+            contract_address,
+            commitment,
+        } => {
+            let deposit = native_payment(&info)?;
+            execute_commit_bid(
+                deps,
+                env,
+                info.sender.to_string(),
+                deposit,
+                token_id,
+                contract_address,
+                commitment,
+            )
+        }
+        ExecuteMsg::RevealBid {
+            token_id,
+            contract_address,
+            amount,
+            salt,
+        } => execute_reveal_bid(deps, env, info, token_id, contract_address, amount, salt),
+        ExecuteMsg::SettleSealedAuction {
+            token_id,
+            contract_address,
+        } => execute_settle_sealed_auction(deps, env, token_id, contract_address),
+        ExecuteMsg::CancelAuction {
+            token_id,
+            contract_address,
+        } => {
+            // the penalty, if any, is paid along with the cancellation
+            let penalty = if info.funds.is_empty() {
+                None
+            } else {
+                Some(native_payment(&info)?)
+            };
+            execute_cancel_auction(
+                deps,
+                env,
+                info.sender.to_string(),
+                penalty,
+                token_id,
+                contract_address,
+            )
+        }
+        ExecuteMsg::UpdateAuction {
+            token_id,
+            contract_address,
+            starting_price,
+            expiration,
+        } => execute_update_auction(
             deps,
             env,
             info,
@@ -103,370 +634,5873 @@ pub fn execute(
             starting_price,
             expiration,
         ),
-        ExecuteMsg::Bid { token_id } => execute_bid(deps, env, info, token_id),
-        ExecuteMsg::Claim { token_id } => execute_claim(deps, env, info, token_id),
+        ExecuteMsg::Claim {
+            token_id,
+            contract_address,
+        } => execute_claim(deps, env, info, token_id, contract_address),
+        ExecuteMsg::UpdateConfig {
+            owner,
+            accepted_denoms,
+            accepted_cw20s,
+            fee_bps,
+            fee_collector,
+            auction_extension,
+            cancel_penalty,
+        } => execute_update_config(
+            deps,
+            info,
+            owner,
+            accepted_denoms,
+            accepted_cw20s,
+            fee_bps,
+            fee_collector,
+            auction_extension,
+            cancel_penalty,
+        ),
+        ExecuteMsg::SetRoyalty {
+            contract_address,
+            recipient,
+            bps,
+        } => execute_set_royalty(deps, info, contract_address, recipient, bps),
+        ExecuteMsg::RemoveRoyalty { contract_address } => {
+            execute_remove_royalty(deps, info, contract_address)
+        }
+        ExecuteMsg::PruneExpired { limit } => execute_prune_expired(deps, env, limit),
+        ExecuteMsg::ClearStaleListings {
+            contract_address,
+            token_id,
+        } => execute_clear_stale_listings(deps, contract_address, token_id),
+        ExecuteMsg::ReceiveNft(msg) => execute_receive_nft(deps, env, info, msg),
+        ExecuteMsg::Receive(msg) => execute_receive(deps, env, info, msg),
+        ExecuteMsg::MakeOffer {
+            token_id,
+            contract_address,
+            expiration,
+        } => {
+            let price = native_payment(&info)?;
+            execute_make_offer(
+                deps,
+                env,
+                info.sender.to_string(),
+                price,
+                token_id,
+                contract_address,
+                expiration,
+            )
+        }
+        ExecuteMsg::CancelOffer {
+            token_id,
+            contract_address,
+        } => execute_cancel_offer(deps, info, token_id, contract_address),
+        ExecuteMsg::AcceptOffer {
+            token_id,
+            contract_address,
+            bidder,
+        } => execute_accept_offer(deps, env, info, token_id, contract_address, bidder),
+        ExecuteMsg::MakeCollectionOffer {
+            collection,
+            price,
+            quantity,
+            expiration,
+        } => {
+            let payment = native_payment(&info)?;
+            execute_make_collection_offer(
+                deps,
+                env,
+                info.sender.to_string(),
+                payment,
+                collection,
+                price,
+                quantity,
+                expiration,
+            )
+        }
+        ExecuteMsg::CancelCollectionOffer { collection } => {
+            execute_cancel_collection_offer(deps, info, collection)
+        }
+        ExecuteMsg::AcceptCollectionOffer {
+            collection,
+            token_id,
+            bidder,
+        } => execute_accept_collection_offer(deps, env, info, collection, token_id, bidder),
     }
 }
 
-pub fn execute_claim(
+pub fn execute_receive(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    token_id: String,
+    msg: Cw20ReceiveMsg,
 ) -> Result<Response, ContractError> {
-    let nft = AUCTION_LIST.load(deps.storage, token_id.clone())?;
-    // check if expired
-    if nft.expiration.is_expired(&env.block) == false {
-        return Err(ContractError::OngoingAuction {});
+    // the cw20 contract sending the tokens is the asset, its sender the buyer
+    let payment = Price::Cw20(Cw20Coin {
+        address: info.sender.to_string(),
+        amount: msg.amount,
+    });
+    match from_binary(&msg.msg)? {
+        ReceiveMsg::Buy {
+            token_id,
+            contract_address,
+        } => execute_buy(deps, env, msg.sender, payment, token_id, contract_address),
+        ReceiveMsg::Bid {
+            token_id,
+            contract_address,
+        } => execute_bid(deps, env, msg.sender, payment, token_id, contract_address),
+        ReceiveMsg::BatchBuy { items, best_effort } => execute_batch_buy(
+            deps,
+            env,
+            msg.sender,
+            vec![payment],
+            items,
+            best_effort.unwrap_or(false),
+        ),
+        ReceiveMsg::BuyBundle { bundle_id } => {
+            execute_buy_bundle(deps, env, msg.sender, payment, bundle_id)
+        }
+        ReceiveMsg::BidBundle { bundle_id } => {
+            execute_bid_bundle(deps, env, msg.sender, payment, bundle_id)
+        }
+        ReceiveMsg::ProposeTrade {
+            offered,
+            requested,
+            counterparty,
+        } => execute_propose_trade(
+            deps,
+            env,
+            msg.sender,
+            Some(payment.clone()),
+            offered,
+            requested,
+            Some(payment),
+            counterparty,
+        ),
+        ReceiveMsg::CancelAuction {
+            token_id,
+            contract_address,
+        } => execute_cancel_auction(
+            deps,
+            env,
+            msg.sender,
+            Some(payment),
+            token_id,
+            contract_address,
+        ),
+        ReceiveMsg::CommitBid {
+            token_id,
+            contract_address,
+            commitment,
+        } => execute_commit_bid(
+            deps,
+            env,
+            msg.sender,
+            payment,
+            token_id,
+            contract_address,
+            commitment,
+        ),
+        ReceiveMsg::MakeOffer {
+            token_id,
+            contract_address,
+            expiration,
+        } => execute_make_offer(
+            deps,
+            env,
+            msg.sender,
+            payment,
+            token_id,
+            contract_address,
+            expiration,
+        ),
+        ReceiveMsg::MakeCollectionOffer {
+            collection,
+            price,
+            quantity,
+            expiration,
+        } => execute_make_collection_offer(
+            deps, env, msg.sender, payment, collection, price, quantity, expiration,
+        ),
     }
-    // get highest bid
-    let highest_bid = HIGHEST_BIDDER.load(deps.storage, token_id.clone())?;
-    let amount = highest_bid.bid;
-    let winner = highest_bid.address;
-    // remove nft from auction list
-    AUCTION_LIST.remove(deps.storage, token_id.clone());
-    // remove highest bidder
-    HIGHEST_BIDDER.remove(deps.storage, token_id.clone());
-
-    Ok(Response::new()
-        // Send funds to the original owner.
-        .add_message(CosmosMsg::Bank(BankMsg::Send {
-            to_address: nft.owner.clone(),
-            amount: vec![amount],
-        }))
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: nft.contract_address,
-            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                recipient: winner,
-                token_id: token_id.clone(),
-            })?,
-            funds: vec![],
-        }))
-        .add_attribute("Action", "Buy")
-        .add_attribute("Buyer", info.sender.to_string())
-        .add_attribute("Seller", nft.owner)
-        .add_attribute("NFT", token_id))
 }
 
-pub fn execute_auction(
-    deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-    token_id: String,
+// Returns an NFT custodied by the marketplace to its owner.
+fn return_escrowed_nft(
     contract_address: String,
-    starting_price: Coin,
-    expiration: Expiration,
-) -> Result<Response, ContractError> {
-    // check if NFT is already listed
-    if AUCTION_LIST.has(deps.storage, token_id.clone()) == true {
-        return Err(ContractError::AlreadyListed {});
-    }
-    // retrieve NFT owner
-    let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
-    // check if sender is owner
-    if info.sender.to_string() != owner {
-        return Err(ContractError::Unauthorized {});
+    token_id: String,
+    owner: String,
+) -> StdResult<CosmosMsg> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: contract_address,
+        msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+            recipient: owner,
+            token_id,
+        })?,
+        funds: vec![],
+    }))
+}
+
+// Sellers of the listings, auctions and bundle a token is in, with whether each escrows it
+fn listed_by(
+    storage: &dyn Storage,
+    contract_address: &str,
+    token_id: &str,
+) -> StdResult<Vec<(String, bool)>> {
+    let key = (contract_address.to_string(), token_id.to_string());
+    let mut sellers = vec![];
+    if let Some(nft) = listings().may_load(storage, key.clone())? {
+        sellers.push((nft.owner, nft.escrowed));
     }
-    // valid starting price
-    if starting_price.amount <= Uint128::from(0 as u32) {
-        return Err(ContractError::InvalidAmount {});
+    if let Some(nft) = auction_listings().may_load(storage, key.clone())? {
+        sellers.push((nft.owner, nft.escrowed));
     }
-    // valid denomination
-    if starting_price.denom != "uusd".to_string() {
-        return Err(ContractError::InvalidDenomination {});
+    if let Some(auction) = SEALED_AUCTIONS.may_load(storage, key.clone())? {
+        sellers.push((auction.owner, auction.escrowed));
     }
-    // valid expiration
-    if expiration.is_expired(&env.block) == true {
-        return Err(ContractError::Expired {});
+    if let Some(bundle_id) = BUNDLED_NFTS.may_load(storage, key)? {
+        let bundle = BUNDLES.load(storage, U64Key::new(bundle_id))?;
+        sellers.push((bundle.owner, false));
     }
-    // check if the marketplace contract has approval
-    let marketplace_address = Approval {
-        spender: env.contract.address.to_string(),
-        expires: expiration,
-    };
-    let approvals = get_token_approval(deps.as_ref(), contract_address.clone(), owner.clone())?;
-    let presence = approvals.contains(&marketplace_address);
-    if presence == false {
-        return Err(ContractError::Unapproved {});
+    Ok(sellers)
+}
+
+// Whether the listings of a token were all left by sellers who no longer own it, as when it
+// moved through an offer or a direct transfer. The marketplace holds escrowed tokens, so
+// their listings never go stale.
+fn listings_stale(
+    deps: Deps,
+    contract_address: &str,
+    token_id: &str,
+    sellers: &[(String, bool)],
+) -> Result<bool, ContractError> {
+    if sellers.iter().any(|(_, escrowed)| *escrowed) {
+        return Ok(false);
     }
+    let owner = get_token_owner(deps, token_id.to_string(), contract_address.to_string())?;
+    Ok(sellers.iter().all(|(seller, _)| *seller != owner))
+}
 
-    // add the nft's components
-    let nft = AuctionNft {
-        token_id: token_id.clone(),
-        owner,
-        contract_address,
-        starting_price: starting_price.clone(),
-        expiration,
-    };
-    // add to auction list
-    AUCTION_LIST.save(deps.storage, token_id.clone(), &nft)?;
-
-    let res = Response::new()
-        .add_attribute("action", "auction")
-        .add_attribute("ID", token_id)
-        .add_attribute("expires", expiration.to_string())
-        .add_attribute("starting_price", starting_price.to_string().clone());
+// A token can only be on sale in one place at a time, since an escrowed token is held for
+// whichever listing received it. Stale listings don't count, the new listing clears them
+// with clear_listings before it is saved.
+fn assert_not_listed(
+    deps: Deps,
+    contract_address: &str,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    let sellers = listed_by(deps.storage, contract_address, token_id)?;
+    if !sellers.is_empty() && !listings_stale(deps, contract_address, token_id, &sellers)? {
+        return Err(ContractError::AlreadyListed {});
+    }
+    Ok(())
+}
 
-    Ok(res)
+// Removes the listings left over once a token changed hands, refunding the bids they held
+fn clear_listings(
+    storage: &mut dyn Storage,
+    contract_address: &str,
+    token_id: &str,
+) -> StdResult<Vec<CosmosMsg>> {
+    let key = (contract_address.to_string(), token_id.to_string());
+    let mut refunds = vec![];
+    listings().remove(storage, key.clone())?;
+    auction_listings().remove(storage, key.clone())?;
+    if let Some(highest_bid) = HIGHEST_BIDDER.may_load(storage, key.clone())? {
+        HIGHEST_BIDDER.remove(storage, key.clone());
+        refunds.push(highest_bid.bid.into_msg(highest_bid.address)?);
+    }
+    if SEALED_AUCTIONS.may_load(storage, key.clone())?.is_some() {
+        SEALED_AUCTIONS.remove(storage, key.clone());
+        let bids = SEALED_BIDS
+            .prefix(key.clone())
+            .range(storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, bid)| bid))
+            .collect::<StdResult<Vec<_>>>()?;
+        for bid in bids {
+            SEALED_BIDS.remove(storage, (key.0.clone(), key.1.clone(), bid.bidder.clone()));
+            refunds.push(bid.deposit.into_msg(bid.bidder)?);
+        }
+    }
+    if let Some(bundle_id) = BUNDLED_NFTS.may_load(storage, key)? {
+        let bundle = BUNDLES.load(storage, U64Key::new(bundle_id))?;
+        remove_bundle(storage, &bundle);
+        if let Some(highest_bid) = BUNDLE_BIDS.may_load(storage, U64Key::new(bundle_id))? {
+            BUNDLE_BIDS.remove(storage, U64Key::new(bundle_id));
+            refunds.push(highest_bid.bid.into_msg(highest_bid.address)?);
+        }
+    }
+    Ok(refunds)
 }
-pub fn execute_bid(
+
+pub fn execute_receive_nft(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
+    msg: Cw721ReceiveMsg,
+) -> Result<Response, ContractError> {
+    // the cw721 contract sending the token is the collection, its sender the owner
+    let contract_address = info.sender.to_string();
+    let token_id = msg.token_id;
+    let owner = msg.sender;
+    match from_binary(&msg.msg)? {
+        ReceiveNftMsg::Sell {
+            price,
+            expiration,
+            reserved_for,
+        } => {
+            // check if NFT is already listed
+            assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
+            // valid price
+            if price.amount().is_zero() {
+                return Err(ContractError::InvalidAmount {});
+            }
+            // valid denomination
+            assert_accepted_denom(deps.as_ref(), &price)?;
+            // valid expiration
+            if expiration.is_expired(&env.block) {
+                return Err(ContractError::Expired {});
+            }
+            let reserved_for = reserved_for
+                .map(|buyer| deps.api.addr_validate(&buyer).map(String::from))
+                .transpose()?;
+            let nft = NFT {
+                token_id: token_id.clone(),
+                owner,
+                contract_address: contract_address.clone(),
+                price: price.clone(),
+                expiration,
+                escrowed: true,
+                dutch_auction: None,
+                reserved_for: reserved_for.clone(),
+            };
+            let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+            listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
+
+            let mut res = Response::new()
+                .add_messages(refunds)
+                .add_attribute("action", "list")
+                .add_attribute("ID", token_id)
+                .add_attribute("expires", expiration.to_string())
+                .add_attribute("price", price.to_string())
+                .add_attribute("escrowed", "true");
+            if let Some(reserved_for) = reserved_for {
+                res = res.add_attribute("reserved_for", reserved_for);
+            }
+            Ok(res)
+        }
+        ReceiveNftMsg::DutchAuction {
+            start_price,
+            end_price,
+            start_time,
+            end_time,
+        } => {
+            // check if NFT is already listed
+            assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
+            let dutch_auction = dutch_auction(
+                deps.as_ref(),
+                &env,
+                &start_price,
+                end_price,
+                start_time,
+                end_time,
+            )?;
+            let nft = NFT {
+                token_id: token_id.clone(),
+                owner,
+                contract_address: contract_address.clone(),
+                price: start_price.clone(),
+                expiration: Expiration::Never {},
+                escrowed: true,
+                dutch_auction: Some(dutch_auction),
+                reserved_for: None,
+            };
+            let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+            listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
+
+            Ok(Response::new()
+                .add_messages(refunds)
+                .add_attribute("action", "dutch_auction")
+                .add_attribute("ID", token_id)
+                .add_attribute("start_price", start_price.to_string())
+                .add_attribute("end_price", end_price.to_string())
+                .add_attribute("start_time", start_time.to_string())
+                .add_attribute("end_time", end_time.to_string())
+                .add_attribute("escrowed", "true"))
+        }
+        ReceiveNftMsg::SealedAuction {
+            min_price,
+            commit_end,
+            reveal_end,
+            pricing,
+        } => {
+            let auction = sealed_auction(
+                deps.as_ref(),
+                &env,
+                token_id,
+                owner,
+                contract_address,
+                min_price,
+                commit_end,
+                reveal_end,
+                pricing,
+                true,
+            )?;
+            let refunds =
+                clear_listings(deps.storage, &auction.contract_address, &auction.token_id)?;
+            SEALED_AUCTIONS.save(
+                deps.storage,
+                (auction.contract_address.clone(), auction.token_id.clone()),
+                &auction,
+            )?;
+
+            Ok(Response::new()
+                .add_messages(refunds)
+                .add_attribute("action", "sealed_auction")
+                .add_attribute("ID", auction.token_id)
+                .add_attribute("min_price", auction.min_price.to_string())
+                .add_attribute("commit_end", auction.commit_end.to_string())
+                .add_attribute("reveal_end", auction.reveal_end.to_string())
+                .add_attribute("escrowed", "true"))
+        }
+        ReceiveNftMsg::Auction {
+            starting_price,
+            min_bid_increment,
+            expiration,
+            reserve_price,
+            buy_now_price,
+        } => {
+            // check if NFT is already listed
+            assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
+            // valid starting price
+            if starting_price.amount().is_zero() {
+                return Err(ContractError::InvalidAmount {});
+            }
+            // valid reserve and buy now prices
+            assert_auction_prices(&starting_price, &reserve_price, buy_now_price)?;
+            // valid denomination
+            assert_accepted_denom(deps.as_ref(), &starting_price)?;
+            // valid expiration
+            assert_auction_expiration(&env, &expiration)?;
+            let nft = AuctionNft {
+                token_id: token_id.clone(),
+                owner,
+                contract_address: contract_address.clone(),
+                starting_price: starting_price.clone(),
+                min_bid_increment,
+                expiration,
+                escrowed: true,
+                reserve_price,
+                buy_now_price,
+                auction_id: next_auction_id(deps.storage)?,
+            };
+            let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+            auction_listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
+
+            Ok(Response::new()
+                .add_messages(refunds)
+                .add_attribute("action", "auction")
+                .add_attribute("ID", token_id)
+                .add_attribute("auction_id", nft.auction_id.to_string())
+                .add_attribute("expires", expiration.to_string())
+                .add_attribute("starting_price", starting_price.to_string())
+                .add_attribute("escrowed", "true"))
+        }
+    }
+}
+
+pub fn execute_prune_expired(
+    deps: DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let expired_listings = listings()
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| {
+            item.as_ref()
+                .map_or(true, |(_, nft)| nft.expiration.is_expired(&env.block))
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut messages = vec![];
+    for (_, nft) in expired_listings.iter() {
+        listings().remove(
+            deps.storage,
+            (nft.contract_address.clone(), nft.token_id.clone()),
+        )?;
+        if nft.escrowed {
+            messages.push(return_escrowed_nft(
+                nft.contract_address.clone(),
+                nft.token_id.clone(),
+                nft.owner.clone(),
+            )?);
+        }
+    }
+    // auctions that received bids have to be settled through Claim instead
+    let expired_auctions = auction_listings()
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| {
+            item.as_ref().map_or(true, |(_, auction_nft)| {
+                auction_nft.expiration.is_expired(&env.block)
+                    && !HIGHEST_BIDDER.has(
+                        deps.storage,
+                        (
+                            auction_nft.contract_address.clone(),
+                            auction_nft.token_id.clone(),
+                        ),
+                    )
+            })
+        })
+        .take(limit.saturating_sub(expired_listings.len()))
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, auction_nft) in expired_auctions.iter() {
+        auction_listings().remove(
+            deps.storage,
+            (
+                auction_nft.contract_address.clone(),
+                auction_nft.token_id.clone(),
+            ),
+        )?;
+        if auction_nft.escrowed {
+            messages.push(return_escrowed_nft(
+                auction_nft.contract_address.clone(),
+                auction_nft.token_id.clone(),
+                auction_nft.owner.clone(),
+            )?);
+        }
+    }
+    // sealed auctions holding deposits have to be settled through SettleSealedAuction
+    let pruned = expired_listings.len() + expired_auctions.len();
+    let expired_sealed_auctions = SEALED_AUCTIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| {
+            item.as_ref().map_or(true, |(_, auction)| {
+                env.block.time >= auction.reveal_end
+                    && SEALED_BIDS
+                        .prefix((auction.contract_address.clone(), auction.token_id.clone()))
+                        .range(deps.storage, None, None, Order::Ascending)
+                        .next()
+                        .is_none()
+            })
+        })
+        .take(limit.saturating_sub(pruned))
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, auction) in expired_sealed_auctions.iter() {
+        SEALED_AUCTIONS.remove(
+            deps.storage,
+            (auction.contract_address.clone(), auction.token_id.clone()),
+        );
+        if auction.escrowed {
+            messages.push(return_escrowed_nft(
+                auction.contract_address.clone(),
+                auction.token_id.clone(),
+                auction.owner.clone(),
+            )?);
+        }
+    }
+    // bundle auctions that received bids have to be settled through ClaimBundle
+    let pruned = pruned + expired_sealed_auctions.len();
+    let expired_bundles = BUNDLES
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| {
+            item.as_ref().map_or(true, |(_, bundle)| {
+                bundle.expiration.is_expired(&env.block)
+                    && !BUNDLE_BIDS.has(deps.storage, U64Key::new(bundle.id))
+            })
+        })
+        .take(limit.saturating_sub(pruned))
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, bundle) in expired_bundles.iter() {
+        remove_bundle(deps.storage, bundle);
+    }
+
+    Ok(Response::new()
+        .add_messages(messages)
+        .add_attribute("action", "prune_expired")
+        .add_attribute("listings", expired_listings.len().to_string())
+        .add_attribute("auction_listings", expired_auctions.len().to_string())
+        .add_attribute("sealed_auctions", expired_sealed_auctions.len().to_string())
+        .add_attribute("bundles", expired_bundles.len().to_string()))
+}
+
+pub fn execute_clear_stale_listings(
+    deps: DepsMut,
+    contract_address: String,
     token_id: String,
 ) -> Result<Response, ContractError> {
-    // check if nft is in the auction list
-    if AUCTION_LIST.has(deps.storage, token_id.clone()) == false {
+    let sellers = listed_by(deps.storage, &contract_address, &token_id)?;
+    if sellers.is_empty() {
         return Err(ContractError::NotListed {});
     }
-    // check expiry
-    let nft = AUCTION_LIST.load(deps.storage, token_id.clone())?;
-    if nft.expiration.is_expired(&env.block) == true {
-        return Err(ContractError::Expired {});
-    }
-    // Check for correct funds
-    if info.funds[0].amount <= Uint128::from(0 as u32) {
-        return Err(ContractError::InvalidAmount {});
+    // listings the seller still owns the NFT of can only be delisted by the seller
+    if !listings_stale(deps.as_ref(), &contract_address, &token_id, &sellers)? {
+        return Err(ContractError::Unauthorized {});
     }
-    // check for correct denom
-    if info.funds[0].denom != "uusd".to_string() {
-        return Err(ContractError::InvalidDenomination {});
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+
+    Ok(Response::new()
+        .add_messages(refunds)
+        .add_attribute("action", "clear_stale_listings")
+        .add_attribute("ID", token_id)
+        .add_attribute("listings", sellers.len().to_string()))
+}
+
+pub fn execute_set_royalty(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract_address: String,
+    recipient: String,
+    bps: u64,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    // only the owner may manage the royalty registry
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
     }
-    // get highest bid
-    let highest_bid = HIGHEST_BIDDER.load(deps.storage, token_id.clone())?;
-    let amount = highest_bid.bid.amount;
-    // check if the bid surpasses the current highest bid
-    if info.funds[0].amount <= amount {
-        return Err(ContractError::UnsurpassedHighestBid {});
+    if bps > MAX_BPS {
+        return Err(ContractError::InvalidRoyalty {});
     }
-    let new_highest_bidder = Bidder {
-        address: info.sender.to_string(),
-        bid: info.funds[0].clone(),
+    let royalty = Royalty {
+        recipient: deps.api.addr_validate(&recipient)?,
+        bps,
     };
-    // replace the previous highest bid with the new one
-    HIGHEST_BIDDER.remove(deps.storage, token_id.clone());
-    HIGHEST_BIDDER.save(deps.storage, token_id.clone(), &new_highest_bidder)?;
+    ROYALTIES.save(deps.storage, contract_address.clone(), &royalty)?;
 
     Ok(Response::new()
-        .add_attribute("Action", "Bid")
-        .add_attribute("Bidder", info.sender.to_string())
+        .add_attribute("action", "set_royalty")
+        .add_attribute("contract_address", contract_address)
+        .add_attribute("recipient", recipient)
+        .add_attribute("bps", bps.to_string()))
+}
+
+pub fn execute_remove_royalty(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    // only the owner may manage the royalty registry
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    ROYALTIES.remove(deps.storage, contract_address.clone());
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_royalty")
+        .add_attribute("contract_address", contract_address))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: Option<String>,
+    accepted_denoms: Option<Vec<String>>,
+    accepted_cw20s: Option<Vec<String>>,
+    fee_bps: Option<u64>,
+    fee_collector: Option<String>,
+    auction_extension: Option<AuctionExtension>,
+    cancel_penalty: Option<CancelPenalty>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    // only the owner may change the config
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    if let Some(owner) = owner {
+        config.owner = deps.api.addr_validate(&owner)?;
+    }
+    if let Some(accepted_denoms) = accepted_denoms {
+        config.accepted_denoms = accepted_denoms;
+    }
+    if let Some(accepted_cw20s) = accepted_cw20s {
+        config.accepted_cw20s = accepted_cw20s;
+    }
+    if let Some(fee_bps) = fee_bps {
+        if fee_bps > MAX_BPS {
+            return Err(ContractError::InvalidFee {});
+        }
+        config.fee_bps = fee_bps;
+    }
+    if let Some(fee_collector) = fee_collector {
+        config.fee_collector = deps.api.addr_validate(&fee_collector)?;
+    }
+    if let Some(auction_extension) = auction_extension {
+        config.auction_extension = auction_extension;
+    }
+    if let Some(cancel_penalty) = cancel_penalty {
+        assert_cancel_penalty(&cancel_penalty)?;
+        config.cancel_penalty = cancel_penalty;
+    }
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_attribute("owner", config.owner)
+        .add_attribute("accepted_denoms", config.accepted_denoms.join(","))
+        .add_attribute("accepted_cw20s", config.accepted_cw20s.join(","))
+        .add_attribute("fee_bps", config.fee_bps.to_string())
+        .add_attribute("fee_collector", config.fee_collector)
+        .add_attribute(
+            "auction_extension",
+            format!(
+                "{}/{}",
+                config.auction_extension.window, config.auction_extension.extension
+            ),
+        )
+        .add_attribute(
+            "cancel_penalty",
+            match config.cancel_penalty {
+                CancelPenalty::Disallowed => "disallowed".to_string(),
+                CancelPenalty::BasisPoints(bps) => bps.to_string(),
+            },
+        ))
+}
+
+pub fn execute_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let nft = auction_listings()
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // check if expired
+    if !nft.expiration.is_expired(&env.block) {
+        return Err(ContractError::OngoingAuction {});
+    }
+    // anyone may settle a finished auction, the proceeds only ever go to its participants
+    let claimed_by = info.sender.to_string();
+    // get highest bid, if any
+    let highest_bid =
+        HIGHEST_BIDDER.may_load(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    // remove nft from auction list
+    auction_listings().remove(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    // without bids the seller keeps the NFT
+    let highest_bid = match highest_bid {
+        Some(highest_bid) => highest_bid,
+        None => {
+            let mut res = Response::new()
+                .add_attribute("Action", "NoBids")
+                .add_attribute("ClaimedBy", claimed_by)
+                .add_attribute("Seller", nft.owner.clone())
+                .add_attribute("NFT", token_id.clone());
+            if nft.escrowed {
+                res = res.add_message(return_escrowed_nft(contract_address, token_id, nft.owner)?);
+            }
+            return Ok(res);
+        }
+    };
+    // remove highest bidder
+    HIGHEST_BIDDER.remove(deps.storage, (contract_address.clone(), token_id.clone()));
+    // an NFT the seller moved or stopped approving can't be handed over
+    let unsellable = !nft.escrowed
+        && !can_transfer(
+            deps.as_ref(),
+            &env,
+            &contract_address,
+            &token_id,
+            &nft.owner,
+        );
+    // below the reserve price the seller keeps the NFT and the bid is refunded
+    if unsellable || !nft.reserve_met(&highest_bid.bid) {
+        let mut res = Response::new()
+            .add_message(
+                highest_bid
+                    .bid
+                    .clone()
+                    .into_msg(highest_bid.address.clone())?,
+            )
+            .add_attribute(
+                "Action",
+                if unsellable {
+                    "Unsellable"
+                } else {
+                    "ReserveNotMet"
+                },
+            )
+            .add_attribute("ClaimedBy", claimed_by)
+            .add_attribute("Refunded", highest_bid.address)
+            .add_attribute("Refund", highest_bid.bid.to_string())
+            .add_attribute("Seller", nft.owner.clone())
+            .add_attribute("NFT", token_id.clone());
+        if nft.escrowed {
+            res = res.add_message(return_escrowed_nft(contract_address, token_id, nft.owner)?);
+        }
+        return Ok(res);
+    }
+    let amount = highest_bid.bid;
+    let winner = highest_bid.address;
+    // split the winning bid between the fee collector and the original owner
+    let payouts = sale_payouts(
+        deps.as_ref(),
+        amount,
+        nft.owner.clone(),
+        token_id.clone(),
+        contract_address.clone(),
+    )?;
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+
+    Ok(payouts
+        .add_to(Response::new())
+        .add_messages(refunds)
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft.contract_address,
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: winner.clone(),
+                token_id: token_id.clone(),
+            })?,
+            funds: vec![],
+        }))
+        .add_attribute("Action", "Buy")
+        .add_attribute("ClaimedBy", claimed_by)
+        .add_attribute("Buyer", winner)
         .add_attribute("Seller", nft.owner)
         .add_attribute("NFT", token_id))
 }
 
-pub fn execute_sell(
+// A reserve price can't be below the starting price, and a buy now price can't be below
+// either of them. A hidden reserve is left unbounded by the public buy now price, which
+// would otherwise give bidders an upper bound on it.
+fn assert_auction_prices(
+    starting_price: &Price,
+    reserve_price: &Option<ReservePrice>,
+    buy_now_price: Option<Uint128>,
+) -> Result<(), ContractError> {
+    let reserve = match reserve_price {
+        Some(reserve_price) if reserve_price.amount < starting_price.amount() => {
+            return Err(ContractError::InvalidAmount {});
+        }
+        Some(reserve_price) if !reserve_price.hidden => reserve_price.amount,
+        _ => starting_price.amount(),
+    };
+    if let Some(buy_now_price) = buy_now_price {
+        if buy_now_price < reserve {
+            return Err(ContractError::InvalidAmount {});
+        }
+    }
+    Ok(())
+}
+
+// An auction has to end, otherwise its bids could never be claimed or refunded
+fn assert_auction_expiration(env: &Env, expiration: &Expiration) -> Result<(), ContractError> {
+    match expiration {
+        Expiration::Never {} => Err(ContractError::CustomError {
+            val: "an auction has to expire".to_string(),
+        }),
+        expiration if expiration.is_expired(&env.block) => Err(ContractError::Expired {}),
+        _ => Ok(()),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_auction(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     token_id: String,
     contract_address: String,
-    price: Coin,
+    starting_price: Price,
+    min_bid_increment: Option<BidIncrement>,
     expiration: Expiration,
+    reserve_price: Option<ReservePrice>,
+    buy_now_price: Option<Uint128>,
 ) -> Result<Response, ContractError> {
     // check if NFT is already listed
-    if LIST.has(deps.storage, token_id.clone()) == true {
-        return Err(ContractError::AlreadyListed {});
-    }
+    assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
     // retrieve NFT owner
     let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
     // check if sender is owner
-    if info.sender.to_string() != owner {
+    if info.sender != owner {
         return Err(ContractError::Unauthorized {});
     }
-    // valid price
-    if price.amount <= Uint128::from(0 as u32) {
+    // valid starting price
+    if starting_price.amount().is_zero() {
         return Err(ContractError::InvalidAmount {});
     }
+    // valid reserve and buy now prices
+    assert_auction_prices(&starting_price, &reserve_price, buy_now_price)?;
     // valid denomination
-    if price.denom != "uusd".to_string() {
-        return Err(ContractError::InvalidDenomination {});
-    }
+    assert_accepted_denom(deps.as_ref(), &starting_price)?;
     // valid expiration
-    if expiration.is_expired(&env.block) == true {
-        return Err(ContractError::Expired {});
-    }
-
-    // transfer permission to the marketplace smart contract by sending a message to the NFT smart contract
+    assert_auction_expiration(&env, &expiration)?;
     // check if the marketplace contract has approval
-    let marketplace_address = Approval {
-        spender: env.contract.address.to_string(),
-        expires: expiration,
-    };
-    let approvals = get_token_approval(deps.as_ref(), contract_address.clone(), owner.clone())?;
-    let presence = approvals.contains(&marketplace_address);
-    if presence == false {
-        return Err(ContractError::Unapproved {});
-    }
+    assert_marketplace_approved(
+        deps.as_ref(),
+        &env,
+        contract_address.clone(),
+        owner.clone(),
+        &expiration,
+    )?;
 
-    // let presence: String = approvals
-    //     .clone()
-    //     .into_iter()
-    //     .map(|x| x.spender)
-    //     .filter(|x| *x == marketplace_address)
-    //     .collect();
-
-    // Implement the NFT's components
-    let nft = NFT {
+    // add the nft's components
+    let nft = AuctionNft {
         token_id: token_id.clone(),
         owner,
-        contract_address,
-        price: price.clone(),
+        contract_address: contract_address.clone(),
+        starting_price: starting_price.clone(),
+        min_bid_increment,
         expiration,
+        escrowed: false,
+        reserve_price,
+        buy_now_price,
+        auction_id: next_auction_id(deps.storage)?,
     };
-    // add the NFT to the list of NFTs for sale
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+    // add to auction list
+    auction_listings().save(
+        deps.storage,
+        (contract_address.clone(), token_id.clone()),
+        &nft,
+    )?;
 
-    LIST.save(deps.storage, token_id.clone(), &nft)?;
-    // send response
     let res = Response::new()
-        .add_attribute("action", "list")
+        .add_messages(refunds)
+        .add_attribute("action", "auction")
         .add_attribute("ID", token_id)
+        .add_attribute("auction_id", nft.auction_id.to_string())
         .add_attribute("expires", expiration.to_string())
-        .add_attribute("price", price.to_string().clone());
+        .add_attribute("starting_price", starting_price.to_string().clone());
 
     Ok(res)
 }
-pub fn execute_delist(
+pub fn execute_cancel_auction(
     deps: DepsMut,
-    _env: Env,
-    info: MessageInfo,
+    env: Env,
+    sender: String,
+    penalty: Option<Price>,
     token_id: String,
     contract_address: String,
 ) -> Result<Response, ContractError> {
-    // check if NFT is already listed
-    if LIST.has(deps.storage, token_id.clone()) == false {
-        return Err(ContractError::NotListed {});
-    }
-    // retrieve NFT owner
-    let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address)?;
-    // check if sender is owner
-    if info.sender.to_string() != owner {
+    let nft = auction_listings()
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // only the seller may cancel
+    if sender != nft.owner {
         return Err(ContractError::Unauthorized {});
     }
-    // remove from list
-    LIST.remove(deps.storage, token_id.clone());
-    Ok(Response::new().add_attribute("Delisted", token_id.clone()))
+    let highest_bid =
+        HIGHEST_BIDDER.may_load(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    let mut res = Response::new()
+        .add_attribute("Action", "CancelAuction")
+        .add_attribute("Seller", nft.owner.clone())
+        .add_attribute("NFT", token_id.clone());
+    match highest_bid {
+        None => {
+            if penalty.is_some() {
+                return Err(ContractError::InvalidFunds {});
+            }
+        }
+        Some(highest_bid) => {
+            // the highest bidder of a finished auction is owed the NFT
+            if nft.expiration.is_expired(&env.block) {
+                return Err(ContractError::Expired {});
+            }
+            let bps = match CONFIG.load(deps.storage)?.cancel_penalty {
+                CancelPenalty::Disallowed => return Err(ContractError::AuctionHasBids {}),
+                CancelPenalty::BasisPoints(bps) => bps,
+            };
+            let amount = highest_bid.bid.amount().multiply_ratio(bps, MAX_BPS);
+            let paid = match &penalty {
+                Some(penalty) if penalty.same_asset(&highest_bid.bid) => penalty.amount(),
+                Some(_) => return Err(ContractError::InvalidDenomination {}),
+                None => Uint128::zero(),
+            };
+            if paid != amount {
+                return Err(ContractError::InvalidFunds {});
+            }
+            HIGHEST_BIDDER.remove(deps.storage, (contract_address.clone(), token_id.clone()));
+            res = res
+                // Refund the escrowed bid of the highest bidder.
+                .add_message(
+                    highest_bid
+                        .bid
+                        .clone()
+                        .into_msg(highest_bid.address.clone())?,
+                )
+                .add_attribute("Refunded", highest_bid.address.clone())
+                .add_attribute("Refund", highest_bid.bid.to_string());
+            if !amount.is_zero() {
+                let penalty = highest_bid.bid.with_amount(amount);
+                res = res
+                    .add_message(penalty.clone().into_msg(highest_bid.address)?)
+                    .add_attribute("Penalty", penalty.to_string());
+            }
+        }
+    }
+    auction_listings().remove(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    if nft.escrowed {
+        res = res.add_message(return_escrowed_nft(contract_address, token_id, nft.owner)?);
+    }
+    Ok(res)
 }
 
-pub fn execute_buy(
+pub fn execute_update_auction(
     deps: DepsMut,
-    _env: Env,
+    env: Env,
     info: MessageInfo,
     token_id: String,
+    contract_address: String,
+    starting_price: Option<Price>,
+    expiration: Option<Expiration>,
 ) -> Result<Response, ContractError> {
-    // check if NFT is listed
-    if LIST.has(deps.storage, token_id.clone()) == false {
-        return Err(ContractError::NotListed {});
+    let mut nft = auction_listings()
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // only the seller may update
+    if info.sender != nft.owner {
+        return Err(ContractError::Unauthorized {});
     }
-    // load the list of NFTs
-    let nft = LIST.load(deps.storage, token_id.clone())?;
-    // check price
-    if info.funds[0].amount != nft.price.amount {
-        return Err(ContractError::InvalidAmount {});
+    // bidders have committed to the current terms
+    if HIGHEST_BIDDER.has(deps.storage, (contract_address.clone(), token_id.clone())) {
+        return Err(ContractError::AuctionHasBids {});
     }
-    // check denom
-    if info.funds[0].denom != "uusd".to_string() {
-        return Err(ContractError::InvalidDenomination {});
+    if let Some(starting_price) = starting_price {
+        // valid starting price
+        if starting_price.amount().is_zero() {
+            return Err(ContractError::InvalidAmount {});
+        }
+        // reserve and buy now prices are in the asset of the starting price
+        if !starting_price.same_asset(&nft.starting_price) {
+            return Err(ContractError::InvalidDenomination {});
+        }
+        nft.starting_price = starting_price;
     }
-    // // check expiration
-    // if nft.expiration.is_expired(&env.block) == true {
-    //     return Err(ContractError::Expired {});
-    // }
-    // remove NFT from list
-    LIST.remove(deps.storage, token_id.clone());
-    // transfer ownership of NFT to buyer
+    assert_auction_prices(&nft.starting_price, &nft.reserve_price, nft.buy_now_price)?;
+    if let Some(expiration) = expiration {
+        // valid expiration
+        assert_auction_expiration(&env, &expiration)?;
+        // check if the marketplace contract still has approval
+        if !nft.escrowed {
+            assert_marketplace_approved(
+                deps.as_ref(),
+                &env,
+                contract_address.clone(),
+                nft.owner.clone(),
+                &expiration,
+            )?;
+        }
+        nft.expiration = expiration;
+    }
+    auction_listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
 
-    // transfer money to seller
     Ok(Response::new()
-        // Send funds to the original owner.
-        .add_message(CosmosMsg::Bank(BankMsg::Send {
-            to_address: nft.owner.clone(),
-            amount: vec![info.funds[0].clone()],
-        }))
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: nft.contract_address,
-            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                recipient: info.sender.to_string(),
-                token_id: token_id.clone(),
-            })?,
-            funds: vec![],
-        }))
-        .add_attribute("Action", "Buy")
-        .add_attribute("Buyer", info.sender.to_string())
-        .add_attribute("Seller", nft.owner)
-        .add_attribute("NFT", token_id))
+        .add_attribute("action", "update_auction")
+        .add_attribute("ID", token_id)
+        .add_attribute("expires", nft.expiration.to_string())
+        .add_attribute("starting_price", nft.starting_price.to_string()))
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetListing { token_id } => to_binary(&query_listing(deps, token_id)?),
-        // QueryMsg::GetListings {} => to_binary(&query_listings(deps)?),
-        QueryMsg::GetAuctionListing { token_id } => {
-            to_binary(&query_auction_listing(deps, token_id)?)
-        }
-        QueryMsg::GetHighestBidder { token_id } => {
-            to_binary(&query_highest_bidder(deps, token_id)?)
+pub fn execute_bid(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    bid: Price,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    // check if nft is in the auction list
+    if !auction_listings()
+        .key((contract_address.clone(), token_id.clone()))
+        .has(deps.storage)
+    {
+        return Err(ContractError::NotListed {});
+    }
+    // check expiry
+    let mut nft =
+        auction_listings().load(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    if nft.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // Check for correct funds
+    if bid.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // check for correct denom
+    if !bid.same_asset(&nft.starting_price) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    // get highest bid, if any
+    let highest_bid =
+        HIGHEST_BIDDER.may_load(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    // a bid meeting the buy now price wins the auction right away
+    if let Some(buy_now_price) = nft.buy_now_price {
+        if bid.amount() >= buy_now_price {
+            record_bid(deps.storage, &env, &nft, &bidder, &bid)?;
+            return settle_buy_now(deps, nft, highest_bid, bidder, bid, buy_now_price);
         }
     }
+    assert_bid_amount(
+        &bid,
+        &nft.starting_price,
+        &nft.min_bid_increment,
+        &highest_bid,
+    )?;
+    record_bid(deps.storage, &env, &nft, &bidder, &bid)?;
+    let new_highest_bidder = Bidder {
+        address: bidder.clone(),
+        bid,
+    };
+    // replace the previous highest bid with the new one
+    HIGHEST_BIDDER.save(
+        deps.storage,
+        (contract_address.clone(), token_id.clone()),
+        &new_highest_bidder,
+    )?;
+    // late bids push the end of the auction back so that others can respond
+    let config = CONFIG.load(deps.storage)?;
+    let extended = config.auction_extension.extend(&nft.expiration, &env.block);
+    if let Some(expiration) = extended {
+        nft.expiration = expiration;
+        auction_listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
+    }
+
+    let mut res = Response::new()
+        .add_attribute("Action", "Bid")
+        .add_attribute("Bidder", bidder)
+        .add_attribute("Seller", nft.owner)
+        .add_attribute("NFT", token_id);
+    if extended.is_some() {
+        res = res.add_attribute("ExtendedTo", nft.expiration.to_string());
+    }
+    if let Some(highest_bid) = highest_bid {
+        res = res
+            // Refund the escrowed bid of the outbid bidder.
+            .add_message(
+                highest_bid
+                    .bid
+                    .clone()
+                    .into_msg(highest_bid.address.clone())?,
+            )
+            .add_attribute("Refunded", highest_bid.address)
+            .add_attribute("Refund", highest_bid.bid.to_string());
+    }
+
+    Ok(res)
 }
-fn query_listing(deps: Deps, token_id: String) -> StdResult<ListingResponse> {
-    let nft = LIST.load(deps.storage, token_id)?;
-    Ok(ListingResponse { nft })
-}
-// fn query_listings(deps: Deps) -> StdResult<ListingsResponse> {
-//     let nft_list = LIST.range(deps.storage, min, None, order)
-// }
-fn query_auction_listing(deps: Deps, token_id: String) -> StdResult<AuctionListingResponse> {
-    let auction_nft = AUCTION_LIST.load(deps.storage, token_id)?;
-    Ok(AuctionListingResponse { auction_nft })
+
+// Checks that a bid meets the starting price, or outbids the highest bid by the minimum
+// increment
+fn assert_bid_amount(
+    bid: &Price,
+    starting_price: &Price,
+    min_bid_increment: &Option<BidIncrement>,
+    highest_bid: &Option<Bidder>,
+) -> Result<(), ContractError> {
+    let min_bid = match highest_bid {
+        // the first bid has to meet the starting price
+        None => starting_price.amount(),
+        Some(highest_bid) => {
+            let amount = highest_bid.bid.amount();
+            // check if the bid surpasses the current highest bid
+            if bid.amount() <= amount {
+                return Err(ContractError::UnsurpassedHighestBid {});
+            }
+            let increment = match min_bid_increment {
+                Some(BidIncrement::Absolute(increment)) => *increment,
+                Some(BidIncrement::BasisPoints(bps)) => amount.multiply_ratio(*bps, MAX_BPS),
+                None => Uint128::zero(),
+            };
+            amount.checked_add(increment).map_err(StdError::from)?
+        }
+    };
+    if bid.amount() < min_bid {
+        return Err(ContractError::BidBelowMinimum { min: min_bid });
+    }
+    Ok(())
 }
-fn query_highest_bidder(deps: Deps, token_id: String) -> StdResult<HighestBidderResponse> {
-    let highest_bidder = HIGHEST_BIDDER.load(deps.storage, token_id)?;
-    Ok(HighestBidderResponse {
-        bidder: highest_bidder,
+
+// Id of a new auction, starting its own bid history
+fn next_auction_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = AUCTION_COUNT.may_load(storage)?.unwrap_or_default();
+    AUCTION_COUNT.save(storage, &(id + 1))?;
+    Ok(id)
+}
+
+// Adds an accepted bid to the auction's bid history
+fn record_bid(
+    storage: &mut dyn Storage,
+    env: &Env,
+    nft: &AuctionNft,
+    bidder: &str,
+    bid: &Price,
+) -> StdResult<()> {
+    let id = BID_COUNT
+        .may_load(storage, U64Key::new(nft.auction_id))?
+        .unwrap_or_default();
+    BID_COUNT.save(storage, U64Key::new(nft.auction_id), &(id + 1))?;
+    let record = BidRecord {
+        id,
+        bidder: bidder.to_string(),
+        bid: bid.clone(),
+        height: env.block.height,
+        time: env.block.time,
+    };
+    BIDS.save(
+        storage,
+        (U64Key::new(nft.auction_id), U64Key::new(id)),
+        &record,
+    )
+}
+
+// Sells an auctioned NFT at its buy now price, refunding the outbid bidder and any overpayment.
+fn settle_buy_now(
+    deps: DepsMut,
+    nft: AuctionNft,
+    highest_bid: Option<Bidder>,
+    bidder: String,
+    bid: Price,
+    buy_now_price: Uint128,
+) -> Result<Response, ContractError> {
+    let key = (nft.contract_address.clone(), nft.token_id.clone());
+    auction_listings().remove(deps.storage, key.clone())?;
+    HIGHEST_BIDDER.remove(deps.storage, key);
+    let payouts = sale_payouts(
+        deps.as_ref(),
+        bid.with_amount(buy_now_price),
+        nft.owner.clone(),
+        nft.token_id.clone(),
+        nft.contract_address.clone(),
+    )?;
+
+    let mut res = payouts
+        .add_to(Response::new())
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft.contract_address,
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: bidder.clone(),
+                token_id: nft.token_id.clone(),
+            })?,
+            funds: vec![],
+        }))
+        .add_attribute("Action", "BuyNow")
+        .add_attribute("Buyer", bidder.clone())
+        .add_attribute("Seller", nft.owner)
+        .add_attribute("NFT", nft.token_id);
+    let excess = bid.amount() - buy_now_price;
+    if !excess.is_zero() {
+        res = res
+            .add_message(bid.with_amount(excess).into_msg(bidder)?)
+            .add_attribute("Excess", excess.to_string());
+    }
+    if let Some(highest_bid) = highest_bid {
+        res = res
+            // Refund the escrowed bid of the outbid bidder.
+            .add_message(
+                highest_bid
+                    .bid
+                    .clone()
+                    .into_msg(highest_bid.address.clone())?,
+            )
+            .add_attribute("Refunded", highest_bid.address)
+            .add_attribute("Refund", highest_bid.bid.to_string());
+    }
+    Ok(res)
+}
+
+// Validates a new sealed-bid auction
+#[allow(clippy::too_many_arguments)]
+fn sealed_auction(
+    deps: Deps,
+    env: &Env,
+    token_id: String,
+    owner: String,
+    contract_address: String,
+    min_price: Price,
+    commit_end: Timestamp,
+    reveal_end: Timestamp,
+    pricing: SealedBidPricing,
+    escrowed: bool,
+) -> Result<SealedAuction, ContractError> {
+    // check if NFT is already listed
+    assert_not_listed(deps, &contract_address, &token_id)?;
+    // valid minimum price
+    if min_price.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps, &min_price)?;
+    // valid phases
+    if commit_end <= env.block.time {
+        return Err(ContractError::Expired {});
+    }
+    if reveal_end <= commit_end {
+        return Err(ContractError::CustomError {
+            val: "commit_end must be before reveal_end".to_string(),
+        });
+    }
+    Ok(SealedAuction {
+        token_id,
+        owner,
+        contract_address,
+        min_price,
+        commit_end,
+        reveal_end,
+        pricing,
+        escrowed,
+        highest_bid: None,
+        second_bid: None,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_sealed_auction(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+    min_price: Price,
+    commit_end: Timestamp,
+    reveal_end: Timestamp,
+    pricing: SealedBidPricing,
+) -> Result<Response, ContractError> {
+    // retrieve NFT owner
+    let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
+    // check if sender is owner
+    if info.sender != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let auction = sealed_auction(
+        deps.as_ref(),
+        &env,
+        token_id,
+        owner,
+        contract_address,
+        min_price,
+        commit_end,
+        reveal_end,
+        pricing,
+        false,
+    )?;
+    // check if the marketplace contract has approval
+    assert_marketplace_approved(
+        deps.as_ref(),
+        &env,
+        auction.contract_address.clone(),
+        auction.owner.clone(),
+        &Expiration::AtTime(auction.reveal_end),
+    )?;
+    let refunds = clear_listings(deps.storage, &auction.contract_address, &auction.token_id)?;
+    SEALED_AUCTIONS.save(
+        deps.storage,
+        (auction.contract_address.clone(), auction.token_id.clone()),
+        &auction,
+    )?;
+
+    Ok(Response::new()
+        .add_messages(refunds)
+        .add_attribute("action", "sealed_auction")
+        .add_attribute("ID", auction.token_id)
+        .add_attribute("min_price", auction.min_price.to_string())
+        .add_attribute("commit_end", auction.commit_end.to_string())
+        .add_attribute("reveal_end", auction.reveal_end.to_string()))
+}
+
+pub fn execute_commit_bid(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    deposit: Price,
+    token_id: String,
+    contract_address: String,
+    commitment: String,
+) -> Result<Response, ContractError> {
+    let auction = SEALED_AUCTIONS
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // check commit phase
+    if env.block.time >= auction.commit_end {
+        return Err(ContractError::Expired {});
+    }
+    // check for correct denom
+    if !deposit.same_asset(&auction.min_price) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    // the deposit has to be able to cover a winning bid
+    if deposit.amount() < auction.min_price.amount() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid commitment
+    let commitment = commitment.to_lowercase();
+    if hex::decode(&commitment).map_or(true, |hash| hash.len() != 32) {
+        return Err(ContractError::CustomError {
+            val: "commitment must be a hex encoded sha256 hash".to_string(),
+        });
+    }
+    let key = (contract_address, token_id.clone(), bidder.clone());
+    if SEALED_BIDS.may_load(deps.storage, key.clone())?.is_some() {
+        return Err(ContractError::AlreadyCommitted {});
+    }
+    let bid = SealedBid {
+        bidder: bidder.clone(),
+        commitment,
+        deposit: deposit.clone(),
+        revealed: None,
+    };
+    SEALED_BIDS.save(deps.storage, key, &bid)?;
+
+    Ok(Response::new()
+        .add_attribute("Action", "CommitBid")
+        .add_attribute("Bidder", bidder)
+        .add_attribute("NFT", token_id)
+        .add_attribute("Deposit", deposit.to_string()))
+}
+
+pub fn execute_reveal_bid(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+    amount: Uint128,
+    salt: String,
+) -> Result<Response, ContractError> {
+    let mut auction = SEALED_AUCTIONS
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // check reveal phase
+    if env.block.time < auction.commit_end {
+        return Err(ContractError::OngoingAuction {});
+    }
+    if env.block.time >= auction.reveal_end {
+        return Err(ContractError::Expired {});
+    }
+    let bidder = info.sender.to_string();
+    let key = (contract_address.clone(), token_id.clone(), bidder.clone());
+    let mut bid = SEALED_BIDS
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::InvalidReveal {})?;
+    // the bid has to match its commitment and be covered by the deposit
+    let hash = Sha256::digest(format!("{}:{}:{}", bidder, amount, salt).as_bytes());
+    if bid.revealed.is_some()
+        || hex::encode(hash) != bid.commitment
+        || amount > bid.deposit.amount()
+    {
+        return Err(ContractError::InvalidReveal {});
+    }
+    bid.revealed = Some(amount);
+    SEALED_BIDS.save(deps.storage, key, &bid)?;
+    // bids below the minimum price are refunded at settlement without competing
+    if amount >= auction.min_price.amount() {
+        match &auction.highest_bid {
+            // ties go to the earlier reveal
+            Some(highest_bid) if amount <= highest_bid.bid.amount() => {
+                if auction.second_bid < Some(amount) {
+                    auction.second_bid = Some(amount);
+                }
+            }
+            _ => {
+                auction.second_bid = auction
+                    .highest_bid
+                    .map(|highest_bid| highest_bid.bid.amount());
+                auction.highest_bid = Some(Bidder {
+                    address: bidder.clone(),
+                    bid: auction.min_price.with_amount(amount),
+                });
+            }
+        }
+        SEALED_AUCTIONS.save(deps.storage, (contract_address, token_id.clone()), &auction)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("Action", "RevealBid")
+        .add_attribute("Bidder", bidder)
+        .add_attribute("NFT", token_id)
+        .add_attribute("Bid", amount.to_string()))
+}
+
+pub fn execute_settle_sealed_auction(
+    deps: DepsMut,
+    env: Env,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let auction = SEALED_AUCTIONS
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // check if the reveal phase is over
+    if env.block.time < auction.reveal_end {
+        return Err(ContractError::OngoingAuction {});
+    }
+    SEALED_AUCTIONS.remove(deps.storage, (contract_address.clone(), token_id.clone()));
+    // an NFT the seller moved or stopped approving can't be sold, so every deposit is refunded
+    let sellable = auction.escrowed
+        || can_transfer(
+            deps.as_ref(),
+            &env,
+            &contract_address,
+            &token_id,
+            &auction.owner,
+        );
+    let winner = auction
+        .highest_bid
+        .clone()
+        .filter(|_| sellable)
+        .map(|highest_bid| {
+            let price = match auction.pricing {
+                SealedBidPricing::FirstPrice => highest_bid.bid.amount(),
+                SealedBidPricing::SecondPrice => auction
+                    .second_bid
+                    .unwrap_or_else(|| auction.min_price.amount()),
+            };
+            (highest_bid.address, price)
+        });
+    let bids = SEALED_BIDS
+        .prefix((contract_address.clone(), token_id.clone()))
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, bid)| bid))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut res = Response::new()
+        .add_attribute("Action", "SettleSealedAuction")
+        .add_attribute("Seller", auction.owner.clone())
+        .add_attribute("NFT", token_id.clone());
+    if !sellable {
+        res = res.add_attribute("Unsellable", "true");
+    }
+    // refund every deposit except the winner's payment and unrevealed deposits
+    let mut forfeited = Uint128::zero();
+    for bid in bids {
+        SEALED_BIDS.remove(
+            deps.storage,
+            (
+                contract_address.clone(),
+                token_id.clone(),
+                bid.bidder.clone(),
+            ),
+        );
+        let refund = match (&winner, bid.revealed) {
+            (Some((address, price)), _) if *address == bid.bidder => bid.deposit.amount() - price,
+            (_, None) if sellable => {
+                forfeited += bid.deposit.amount();
+                Uint128::zero()
+            }
+            _ => bid.deposit.amount(),
+        };
+        if !refund.is_zero() {
+            res = res.add_message(bid.deposit.with_amount(refund).into_msg(bid.bidder)?);
+        }
+    }
+    if !forfeited.is_zero() {
+        res = res
+            .add_message(
+                auction
+                    .min_price
+                    .with_amount(forfeited)
+                    .into_msg(auction.owner.clone())?,
+            )
+            .add_attribute("Forfeited", forfeited.to_string());
+    }
+    match winner {
+        Some((winner, price)) => {
+            let payouts = sale_payouts(
+                deps.as_ref(),
+                auction.min_price.with_amount(price),
+                auction.owner,
+                token_id.clone(),
+                contract_address.clone(),
+            )?;
+            let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+            res = payouts
+                .add_to(res)
+                .add_messages(refunds)
+                .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: contract_address,
+                    msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                        recipient: winner.clone(),
+                        token_id,
+                    })?,
+                    funds: vec![],
+                }))
+                .add_attribute("Buyer", winner)
+                .add_attribute("Price", price.to_string());
+        }
+        // without a winning bid the seller keeps the NFT
+        None if auction.escrowed => {
+            res = res.add_message(return_escrowed_nft(
+                contract_address,
+                token_id,
+                auction.owner,
+            )?);
+        }
+        None => {}
+    }
+    Ok(res)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_sell(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+    price: Price,
+    expiration: Expiration,
+    reserved_for: Option<String>,
+) -> Result<Response, ContractError> {
+    let approvals = get_token_approval(
+        deps.as_ref(),
+        contract_address.clone(),
+        info.sender.to_string(),
+    )?;
+    let refunds = list_nft(
+        deps,
+        &env,
+        &info.sender,
+        token_id.clone(),
+        contract_address,
+        price.clone(),
+        expiration,
+        reserved_for.clone(),
+        &approvals,
+    )?;
+    // send response
+    let mut res = Response::new()
+        .add_messages(refunds)
+        .add_attribute("action", "list")
+        .add_attribute("ID", token_id)
+        .add_attribute("expires", expiration.to_string())
+        .add_attribute("price", price.to_string().clone());
+    if let Some(reserved_for) = reserved_for {
+        res = res.add_attribute("reserved_for", reserved_for);
+    }
+
+    Ok(res)
+}
+
+// Lists an NFT of the seller, given the seller's operator approvals on its collection
+#[allow(clippy::too_many_arguments)]
+fn list_nft(
+    deps: DepsMut,
+    env: &Env,
+    seller: &Addr,
+    token_id: String,
+    contract_address: String,
+    price: Price,
+    expiration: Expiration,
+    reserved_for: Option<String>,
+    approvals: &[Approval],
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    // check if NFT is already listed
+    assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
+    // retrieve NFT owner
+    let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
+    // check if sender is owner
+    if *seller != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // valid price
+    if price.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps.as_ref(), &price)?;
+    // valid expiration
+    if expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+
+    // transfer permission to the marketplace smart contract by sending a message to the NFT smart contract
+    // check if the marketplace contract has approval
+    if !marketplace_approved_until(env, approvals, &expiration) {
+        return Err(ContractError::Unapproved {});
+    }
+    let reserved_for = reserved_for
+        .map(|buyer| deps.api.addr_validate(&buyer).map(String::from))
+        .transpose()?;
+
+    // let presence: String = approvals
+    //     .clone()
+    //     .into_iter()
+    //     .map(|x| x.spender)
+    //     .filter(|x| *x == marketplace_address)
+    //     .collect();
+
+    // Implement the NFT's components
+    let nft = NFT {
+        token_id: token_id.clone(),
+        owner,
+        contract_address: contract_address.clone(),
+        price: price.clone(),
+        expiration,
+        escrowed: false,
+        dutch_auction: None,
+        reserved_for,
+    };
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+    // add the NFT to the list of NFTs for sale
+
+    listings().save(deps.storage, (contract_address, token_id), &nft)?;
+    Ok(refunds)
+}
+pub fn execute_update_price(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    collection: String,
+    token_id: String,
+    price: Price,
+    expiration: Expiration,
+) -> Result<Response, ContractError> {
+    let mut nft = listings()
+        .may_load(deps.storage, (collection.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // retrieve NFT owner, the marketplace holds escrowed NFTs on the seller's behalf
+    let owner = if nft.escrowed {
+        nft.owner.clone()
+    } else {
+        get_token_owner(deps.as_ref(), token_id.clone(), collection.clone())?
+    };
+    // check if sender is owner and still the seller of the listing
+    if info.sender != owner || info.sender != nft.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // the price of a dutch auction follows its schedule
+    if nft.dutch_auction.is_some() {
+        return Err(ContractError::CustomError {
+            val: "dutch auction prices can't be updated".to_string(),
+        });
+    }
+    // valid price
+    if price.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps.as_ref(), &price)?;
+    // valid expiration
+    if expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // check if the marketplace contract has approval
+    if !nft.escrowed {
+        assert_marketplace_approved(deps.as_ref(), &env, collection.clone(), owner, &expiration)?;
+    }
+    nft.price = price.clone();
+    nft.expiration = expiration;
+    listings().save(deps.storage, (collection, token_id.clone()), &nft)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_price")
+        .add_attribute("ID", token_id)
+        .add_attribute("expires", expiration.to_string())
+        .add_attribute("price", price.to_string()))
+}
+
+// Validates the price schedule of a dutch auction
+fn dutch_auction(
+    deps: Deps,
+    env: &Env,
+    start_price: &Price,
+    end_price: Uint128,
+    start_time: Timestamp,
+    end_time: Timestamp,
+) -> Result<DutchAuction, ContractError> {
+    // the price has to go down, but never to nothing
+    if end_price.is_zero() || start_price.amount() <= end_price {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps, start_price)?;
+    // valid schedule
+    if start_time >= end_time {
+        return Err(ContractError::CustomError {
+            val: "start_time must be before end_time".to_string(),
+        });
+    }
+    if end_time <= env.block.time {
+        return Err(ContractError::Expired {});
+    }
+    Ok(DutchAuction {
+        end_price,
+        start_time,
+        end_time,
     })
 }
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use cosmwasm_std::testing::{
-//         mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info,
-//     };
-//     use cosmwasm_std::{coin, coins, from_binary, CosmosMsg, WasmMsg};
-
-//     const CONTRACT_NAME: &str = "Marketplace";
-//     const CONTRACT_VERSION: &str = "The First";
-
-//     #[test]
-
-//     fn proper_initialization() {
-//         let mut deps = mock_dependencies();
-
-//         // Instantiate an empty contract
-//         let instantiate_msg = InstantiateMsg {};
-//         let info = mock_info("anyone", &[]);
-//         let res = instantiate(deps.as_mut(), mock_env(), info, instantiate_msg).unwrap();
-//         assert_eq!(0, res.messages.len());
-//     }
-
-//     #[test]
-
-//     fn proper_sell() {
-//         let mut deps = mock_dependencies();
-//         let mut depss = mock_dependencies();
-//         const CONTRACT: &str = "HEY";
-
-//         let info = mock_info("anyone", &[]);
-//         let msg = ExecuteMsg::Sell {
-//             token_id: String::from("LINK"),
-//             contract_address: String::from(CONTRACT),
-//             price: coin(100, "UST"),
-//             expiration: cw721::Expiration::AtHeight(90000),
-//         };
-//         let _res = instantiate(deps.as_mut(), mock_env(), info.clone(), InstantiateMsg {}).unwrap();
-//         // sender has to be owner of NFT
-
-//         let err = execute(deps.as_mut(), depss.as_mut(), mock_env(), info, msg).unwrap_err();
-//         assert_eq!(err, ContractError::Unauthorized {});
-//     }
-// }
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_dutch_auction(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+    start_price: Price,
+    end_price: Uint128,
+    start_time: Timestamp,
+    end_time: Timestamp,
+) -> Result<Response, ContractError> {
+    // check if NFT is already listed
+    assert_not_listed(deps.as_ref(), &contract_address, &token_id)?;
+    // retrieve NFT owner
+    let owner = get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
+    // check if sender is owner
+    if info.sender != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let dutch_auction = dutch_auction(
+        deps.as_ref(),
+        &env,
+        &start_price,
+        end_price,
+        start_time,
+        end_time,
+    )?;
+    // check if the marketplace contract has approval, dutch listings don't expire
+    assert_marketplace_approved(
+        deps.as_ref(),
+        &env,
+        contract_address.clone(),
+        owner.clone(),
+        &Expiration::Never {},
+    )?;
+
+    let nft = NFT {
+        token_id: token_id.clone(),
+        owner,
+        contract_address: contract_address.clone(),
+        price: start_price.clone(),
+        expiration: Expiration::Never {},
+        escrowed: false,
+        dutch_auction: Some(dutch_auction),
+        reserved_for: None,
+    };
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+    listings().save(deps.storage, (contract_address, token_id.clone()), &nft)?;
+
+    Ok(Response::new()
+        .add_messages(refunds)
+        .add_attribute("action", "dutch_auction")
+        .add_attribute("ID", token_id)
+        .add_attribute("start_price", start_price.to_string())
+        .add_attribute("end_price", end_price.to_string())
+        .add_attribute("start_time", start_time.to_string())
+        .add_attribute("end_time", end_time.to_string()))
+}
+pub fn execute_delist(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let escrow_return = delist_nft(deps, &info.sender, token_id.clone(), contract_address)?;
+    Ok(Response::new()
+        .add_attribute("Delisted", token_id)
+        .add_messages(escrow_return))
+}
+
+// Removes a listing of the sender, returning the message handing back an escrowed NFT
+fn delist_nft(
+    deps: DepsMut,
+    sender: &Addr,
+    token_id: String,
+    contract_address: String,
+) -> Result<Option<CosmosMsg>, ContractError> {
+    // check if NFT is already listed
+    let nft = listings()
+        .may_load(deps.storage, (contract_address.clone(), token_id.clone()))?
+        .ok_or(ContractError::NotListed {})?;
+    // retrieve NFT owner, the marketplace holds escrowed NFTs on the seller's behalf
+    let owner = if nft.escrowed {
+        nft.owner.clone()
+    } else {
+        get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?
+    };
+    // check if sender is owner
+    if *sender != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // remove from list
+    listings().remove(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    Ok(if nft.escrowed {
+        Some(return_escrowed_nft(contract_address, token_id, owner)?)
+    } else {
+        None
+    })
+}
+
+pub fn execute_buy(
+    deps: DepsMut,
+    env: Env,
+    buyer: String,
+    payment: Price,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let (nft, price) = listing_price(
+        deps.as_ref(),
+        &env,
+        token_id.clone(),
+        contract_address.clone(),
+    )?;
+    // check price, dutch auctions take anything above their current price
+    let accepted = match nft.dutch_auction {
+        Some(_) => payment.amount() >= price.amount(),
+        None => payment.amount() == price.amount(),
+    };
+    if !accepted {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // check denom
+    if !payment.same_asset(&price) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    assert_buyable(deps.as_ref(), &buyer, &nft)?;
+    // remove NFT from list
+    listings().remove(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    // transfer ownership of NFT to buyer
+
+    // transfer money to seller, minus the marketplace fee
+    let excess = payment.amount() - price.amount();
+    let payouts = sale_payouts(
+        deps.as_ref(),
+        price,
+        nft.owner.clone(),
+        token_id.clone(),
+        contract_address,
+    )?;
+    let mut res = Response::new();
+    if !excess.is_zero() {
+        res = res
+            .add_message(payment.with_amount(excess).into_msg(buyer.clone())?)
+            .add_attribute("Excess", excess.to_string());
+    }
+    Ok(payouts
+        .add_to(res)
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: nft.contract_address,
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: buyer.clone(),
+                token_id: token_id.clone(),
+            })?,
+            funds: vec![],
+        }))
+        .add_attribute("Action", "Buy")
+        .add_attribute("Buyer", buyer)
+        .add_attribute("Seller", nft.owner)
+        .add_attribute("NFT", token_id))
+}
+
+// Loads a listing that can be bought in this block along with its current price
+fn listing_price(
+    deps: Deps,
+    env: &Env,
+    token_id: String,
+    contract_address: String,
+) -> Result<(NFT, Price), ContractError> {
+    // check if NFT is listed
+    let nft = listings()
+        .may_load(deps.storage, (contract_address, token_id))?
+        .ok_or(ContractError::NotListed {})?;
+    // dutch auctions can't be bought before they start
+    if let Some(dutch_auction) = &nft.dutch_auction {
+        if env.block.time < dutch_auction.start_time {
+            return Err(ContractError::AuctionNotStarted {});
+        }
+    }
+    // check expiration
+    if nft.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let price = nft.current_price(&env.block);
+    Ok((nft, price))
+}
+
+// Checks, once the payment is, that buyer may take over a listing
+fn assert_buyable(deps: Deps, buyer: &str, nft: &NFT) -> Result<(), ContractError> {
+    // a listing the seller no longer owns the NFT of is stale
+    if !nft.escrowed
+        && !get_token_owner(deps, nft.token_id.clone(), nft.contract_address.clone())
+            .map_or(false, |owner| owner == nft.owner)
+    {
+        return Err(ContractError::NotListed {});
+    }
+    // private listings are only sold to the buyer they are reserved for
+    if let Some(reserved_for) = &nft.reserved_for {
+        if reserved_for != buyer {
+            return Err(ContractError::ReservedForAnotherBuyer {});
+        }
+    }
+    Ok(())
+}
+
+pub fn execute_batch_sell(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    items: Vec<SellItem>,
+    best_effort: bool,
+) -> Result<Response, ContractError> {
+    // operator approvals are queried once per collection
+    let mut approvals: BTreeMap<String, Vec<Approval>> = BTreeMap::new();
+    let mut res = Response::new().add_attribute("action", "batch_list");
+    for item in items {
+        let key = format!("{}:{}", item.contract_address, item.token_id);
+        if !approvals.contains_key(&item.contract_address) {
+            let collection_approvals = get_token_approval(
+                deps.as_ref(),
+                item.contract_address.clone(),
+                info.sender.to_string(),
+            )?;
+            approvals.insert(item.contract_address.clone(), collection_approvals);
+        }
+        let listed = list_nft(
+            deps.branch(),
+            &env,
+            &info.sender,
+            item.token_id,
+            item.contract_address.clone(),
+            item.price,
+            item.expiration,
+            item.reserved_for,
+            &approvals[&item.contract_address],
+        );
+        res = match listed {
+            Ok(refunds) => res.add_messages(refunds).add_attribute("Listed", key),
+            Err(err) if best_effort => res.add_attribute("Failed", format!("{}: {}", key, err)),
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(res)
+}
+
+pub fn execute_batch_delist(
+    mut deps: DepsMut,
+    info: MessageInfo,
+    items: Vec<ListingKey>,
+    best_effort: bool,
+) -> Result<Response, ContractError> {
+    let mut res = Response::new().add_attribute("action", "batch_delist");
+    for item in items {
+        let key = format!("{}:{}", item.contract_address, item.token_id);
+        let delisted = delist_nft(
+            deps.branch(),
+            &info.sender,
+            item.token_id,
+            item.contract_address,
+        );
+        res = match delisted {
+            Ok(escrow_return) => res
+                .add_attribute("Delisted", key)
+                .add_messages(escrow_return),
+            Err(err) if best_effort => res.add_attribute("Failed", format!("{}: {}", key, err)),
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(res)
+}
+
+// Buys every item out of the payment, which can hold several native coins, and refunds
+// whatever is left over
+pub fn execute_batch_buy(
+    deps: DepsMut,
+    env: Env,
+    buyer: String,
+    payment: Vec<Price>,
+    items: Vec<ListingKey>,
+    best_effort: bool,
+) -> Result<Response, ContractError> {
+    let mut balances = payment;
+    let mut transfers = vec![];
+    let mut nft_transfers = vec![];
+    let mut res = Response::new()
+        .add_attribute("Action", "BatchBuy")
+        .add_attribute("Buyer", buyer.clone());
+    for item in items {
+        let key = format!("{}:{}", item.contract_address, item.token_id);
+        match batch_buy_item(deps.as_ref(), &env, &buyer, &mut balances, &item) {
+            Ok((nft, payouts)) => {
+                listings().remove(
+                    deps.storage,
+                    (nft.contract_address.clone(), nft.token_id.clone()),
+                )?;
+                transfers.extend(payouts.transfers);
+                nft_transfers.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: nft.contract_address,
+                    msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                        recipient: buyer.clone(),
+                        token_id: nft.token_id,
+                    })?,
+                    funds: vec![],
+                }));
+                res = res.add_attribute("Bought", key);
+            }
+            Err(err) if best_effort => {
+                res = res.add_attribute("Failed", format!("{}: {}", key, err));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    // refund what is left of the payment
+    transfers.extend(balances.into_iter().map(|balance| (buyer.clone(), balance)));
+    Ok(res
+        .add_messages(merge_transfers(transfers)?)
+        .add_messages(nft_transfers))
+}
+
+pub fn execute_list_bundle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    nfts: Vec<BundleItem>,
+    price: Price,
+    kind: BundleKind,
+    expiration: Expiration,
+) -> Result<Response, ContractError> {
+    // a bundle holds distinct NFTs
+    if nfts.is_empty() {
+        return Err(ContractError::CustomError {
+            val: "a bundle needs at least one NFT".to_string(),
+        });
+    }
+    assert_distinct_nfts(&nfts)?;
+    // check if any NFT is already listed
+    for nft in &nfts {
+        assert_not_listed(deps.as_ref(), &nft.contract_address, &nft.token_id)?;
+    }
+    // valid price
+    if price.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps.as_ref(), &price)?;
+    // valid expiration
+    if expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // the sender has to own every NFT and the marketplace has to be approved for each
+    // collection, whose approvals are queried once
+    let mut approved: BTreeMap<String, bool> = BTreeMap::new();
+    for nft in &nfts {
+        let owner = get_token_owner(
+            deps.as_ref(),
+            nft.token_id.clone(),
+            nft.contract_address.clone(),
+        )?;
+        if info.sender != owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !approved.contains_key(&nft.contract_address) {
+            let approvals = get_token_approval(deps.as_ref(), nft.contract_address.clone(), owner)?;
+            approved.insert(
+                nft.contract_address.clone(),
+                marketplace_approved_until(&env, &approvals, &expiration),
+            );
+        }
+        if !approved[&nft.contract_address] {
+            return Err(ContractError::Unapproved {});
+        }
+    }
+
+    let mut refunds = vec![];
+    for nft in &nfts {
+        refunds.extend(clear_listings(
+            deps.storage,
+            &nft.contract_address,
+            &nft.token_id,
+        )?);
+    }
+    let id = BUNDLE_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    BUNDLE_COUNT.save(deps.storage, &(id + 1))?;
+    let bundle = Bundle {
+        id,
+        owner: info.sender.to_string(),
+        nfts,
+        price: price.clone(),
+        kind,
+        expiration,
+    };
+    BUNDLES.save(deps.storage, U64Key::new(id), &bundle)?;
+    for nft in &bundle.nfts {
+        BUNDLED_NFTS.save(
+            deps.storage,
+            (nft.contract_address.clone(), nft.token_id.clone()),
+            &id,
+        )?;
+    }
+
+    Ok(Response::new()
+        .add_messages(refunds)
+        .add_attribute("action", "list_bundle")
+        .add_attribute("bundle_id", id.to_string())
+        .add_attribute("size", bundle.nfts.len().to_string())
+        .add_attribute("expires", expiration.to_string())
+        .add_attribute("price", price.to_string()))
+}
+
+// Pays out a bundle sale and transfers its NFTs to the buyer. The price is split evenly
+// across the NFTs so that each collection's royalty applies to its share.
+fn settle_bundle(
+    deps: DepsMut,
+    bundle: Bundle,
+    payment: Price,
+    buyer: String,
+) -> Result<Response, ContractError> {
+    let count = Uint128::from(bundle.nfts.len() as u64);
+    let share = payment.amount() / count;
+    let mut remainder = payment.amount() - share * count;
+    let mut transfers = vec![];
+    let mut refunds = vec![];
+    for nft in &bundle.nfts {
+        let amount = share + remainder;
+        remainder = Uint128::zero();
+        let payouts = sale_payouts(
+            deps.as_ref(),
+            payment.with_amount(amount),
+            bundle.owner.clone(),
+            nft.token_id.clone(),
+            nft.contract_address.clone(),
+        )?;
+        transfers.extend(payouts.transfers);
+        refunds.extend(clear_listings(
+            deps.storage,
+            &nft.contract_address,
+            &nft.token_id,
+        )?);
+    }
+
+    Ok(Response::new()
+        .add_messages(merge_transfers(transfers)?)
+        .add_messages(refunds)
+        .add_messages(transfer_nfts(bundle.nfts, &buyer)?)
+        .add_attribute("Buyer", buyer)
+        .add_attribute("Seller", bundle.owner)
+        .add_attribute("bundle_id", bundle.id.to_string())
+        .add_attribute("Price", payment.to_string()))
+}
+
+pub fn execute_buy_bundle(
+    deps: DepsMut,
+    env: Env,
+    buyer: String,
+    payment: Price,
+    bundle_id: u64,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES
+        .may_load(deps.storage, U64Key::new(bundle_id))?
+        .ok_or(ContractError::NotListed {})?;
+    // auctioned bundles are sold through bids
+    if bundle.kind != BundleKind::FixedPrice {
+        return Err(ContractError::OngoingAuction {});
+    }
+    // check price
+    if payment.amount() != bundle.price.amount() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // check denom
+    if !payment.same_asset(&bundle.price) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    // check expiration
+    if bundle.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    remove_bundle(deps.storage, &bundle);
+    let res = settle_bundle(deps, bundle, payment, buyer)?;
+    Ok(res.add_attribute("Action", "BuyBundle"))
+}
+
+pub fn execute_bid_bundle(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    bid: Price,
+    bundle_id: u64,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES
+        .may_load(deps.storage, U64Key::new(bundle_id))?
+        .ok_or(ContractError::NotListed {})?;
+    let min_bid_increment = match bundle.kind {
+        BundleKind::Auction { min_bid_increment } => min_bid_increment,
+        BundleKind::FixedPrice => return Err(ContractError::NotListed {}),
+    };
+    // check expiry
+    if bundle.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // check for correct denom
+    if !bid.same_asset(&bundle.price) {
+        return Err(ContractError::InvalidDenomination {});
+    }
+    let highest_bid = BUNDLE_BIDS.may_load(deps.storage, U64Key::new(bundle_id))?;
+    assert_bid_amount(&bid, &bundle.price, &min_bid_increment, &highest_bid)?;
+    BUNDLE_BIDS.save(
+        deps.storage,
+        U64Key::new(bundle_id),
+        &Bidder {
+            address: bidder.clone(),
+            bid,
+        },
+    )?;
+
+    let mut res = Response::new()
+        .add_attribute("Action", "BidBundle")
+        .add_attribute("Bidder", bidder)
+        .add_attribute("Seller", bundle.owner)
+        .add_attribute("bundle_id", bundle_id.to_string());
+    if let Some(highest_bid) = highest_bid {
+        res = res
+            // Refund the escrowed bid of the outbid bidder.
+            .add_message(
+                highest_bid
+                    .bid
+                    .clone()
+                    .into_msg(highest_bid.address.clone())?,
+            )
+            .add_attribute("Refunded", highest_bid.address)
+            .add_attribute("Refund", highest_bid.bid.to_string());
+    }
+    Ok(res)
+}
+
+pub fn execute_claim_bundle(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    bundle_id: u64,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES
+        .may_load(deps.storage, U64Key::new(bundle_id))?
+        .ok_or(ContractError::NotListed {})?;
+    if bundle.kind == BundleKind::FixedPrice {
+        return Err(ContractError::NotListed {});
+    }
+    // check if expired
+    if !bundle.expiration.is_expired(&env.block) {
+        return Err(ContractError::OngoingAuction {});
+    }
+    remove_bundle(deps.storage, &bundle);
+    let highest_bid = BUNDLE_BIDS.may_load(deps.storage, U64Key::new(bundle_id))?;
+    let res = match highest_bid {
+        // NFTs the seller moved or stopped approving can't be handed over
+        Some(highest_bid)
+            if assert_owned_and_approved(deps.as_ref(), &env, &bundle.owner, &bundle.nfts)
+                .is_err() =>
+        {
+            BUNDLE_BIDS.remove(deps.storage, U64Key::new(bundle_id));
+            Response::new()
+                .add_message(
+                    highest_bid
+                        .bid
+                        .clone()
+                        .into_msg(highest_bid.address.clone())?,
+                )
+                .add_attribute("Action", "Unsellable")
+                .add_attribute("Refunded", highest_bid.address)
+                .add_attribute("Refund", highest_bid.bid.to_string())
+                .add_attribute("Seller", bundle.owner)
+                .add_attribute("bundle_id", bundle_id.to_string())
+        }
+        Some(highest_bid) => {
+            BUNDLE_BIDS.remove(deps.storage, U64Key::new(bundle_id));
+            settle_bundle(deps, bundle, highest_bid.bid, highest_bid.address)?
+                .add_attribute("Action", "ClaimBundle")
+        }
+        // without bids the seller keeps the NFTs
+        None => Response::new()
+            .add_attribute("Action", "NoBids")
+            .add_attribute("Seller", bundle.owner)
+            .add_attribute("bundle_id", bundle_id.to_string()),
+    };
+    Ok(res.add_attribute("ClaimedBy", info.sender))
+}
+
+pub fn execute_delist_bundle(
+    deps: DepsMut,
+    info: MessageInfo,
+    bundle_id: u64,
+) -> Result<Response, ContractError> {
+    let bundle = BUNDLES
+        .may_load(deps.storage, U64Key::new(bundle_id))?
+        .ok_or(ContractError::NotListed {})?;
+    // check if sender is the seller
+    if info.sender != bundle.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // bidders are owed the outcome of the auction
+    if BUNDLE_BIDS
+        .may_load(deps.storage, U64Key::new(bundle_id))?
+        .is_some()
+    {
+        return Err(ContractError::AuctionHasBids {});
+    }
+    remove_bundle(deps.storage, &bundle);
+    Ok(Response::new().add_attribute("DelistedBundle", bundle_id.to_string()))
+}
+
+// Takes a bundle off the market, freeing its NFTs to be listed again
+fn remove_bundle(storage: &mut dyn Storage, bundle: &Bundle) {
+    BUNDLES.remove(storage, U64Key::new(bundle.id));
+    for nft in &bundle.nfts {
+        BUNDLED_NFTS.remove(
+            storage,
+            (nft.contract_address.clone(), nft.token_id.clone()),
+        );
+    }
+}
+
+fn assert_distinct_nfts(nfts: &[BundleItem]) -> Result<(), ContractError> {
+    for (i, nft) in nfts.iter().enumerate() {
+        if nfts[..i].contains(nft) {
+            return Err(ContractError::CustomError {
+                val: format!("{} is in the list twice", nft.token_id),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Checks that owner holds every NFT and has approved the marketplace to transfer them,
+// querying the approvals of each collection once.
+fn assert_owned_and_approved(
+    deps: Deps,
+    env: &Env,
+    owner: &str,
+    nfts: &[BundleItem],
+) -> Result<(), ContractError> {
+    let mut approved: BTreeMap<String, bool> = BTreeMap::new();
+    for nft in nfts {
+        let token_owner =
+            get_token_owner(deps, nft.token_id.clone(), nft.contract_address.clone())?;
+        if token_owner != owner {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !approved.contains_key(&nft.contract_address) {
+            let approvals =
+                get_token_approval(deps, nft.contract_address.clone(), owner.to_string())?;
+            approved.insert(
+                nft.contract_address.clone(),
+                marketplace_approved(env, &approvals),
+            );
+        }
+        if !approved[&nft.contract_address] {
+            return Err(ContractError::Unapproved {});
+        }
+    }
+    Ok(())
+}
+
+fn transfer_nfts(nfts: Vec<BundleItem>, recipient: &str) -> StdResult<Vec<CosmosMsg>> {
+    nfts.into_iter()
+        .map(|nft| {
+            Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: nft.contract_address,
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: recipient.to_string(),
+                    token_id: nft.token_id,
+                })?,
+                funds: vec![],
+            }))
+        })
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_propose_trade(
+    deps: DepsMut,
+    env: Env,
+    proposer: String,
+    payment: Option<Price>,
+    offered: Vec<BundleItem>,
+    requested: Vec<BundleItem>,
+    extra_funds: Option<Price>,
+    counterparty: String,
+) -> Result<Response, ContractError> {
+    let counterparty = deps.api.addr_validate(&counterparty)?.to_string();
+    if counterparty == proposer {
+        return Err(ContractError::CustomError {
+            val: "cannot trade with yourself".to_string(),
+        });
+    }
+    // the escrowed funds have to be the ones announced
+    if payment != extra_funds {
+        return Err(ContractError::InvalidFunds {});
+    }
+    if let Some(extra_funds) = &extra_funds {
+        if extra_funds.amount().is_zero() {
+            return Err(ContractError::InvalidAmount {});
+        }
+        assert_accepted_denom(deps.as_ref(), extra_funds)?;
+    }
+    // something has to be given for something
+    if requested.is_empty() || (offered.is_empty() && extra_funds.is_none()) {
+        return Err(ContractError::CustomError {
+            val: "a trade needs requested NFTs and something offered".to_string(),
+        });
+    }
+    assert_distinct_nfts(&offered)?;
+    assert_distinct_nfts(&requested)?;
+    assert_owned_and_approved(deps.as_ref(), &env, &proposer, &offered)?;
+    for nft in &requested {
+        let owner = get_token_owner(
+            deps.as_ref(),
+            nft.token_id.clone(),
+            nft.contract_address.clone(),
+        )?;
+        if owner != counterparty {
+            return Err(ContractError::CustomError {
+                val: format!("{} is not owned by the counterparty", nft.token_id),
+            });
+        }
+    }
+
+    let id = TRADE_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    TRADE_COUNT.save(deps.storage, &(id + 1))?;
+    let trade = Trade {
+        id,
+        proposer,
+        counterparty,
+        offered,
+        requested,
+        extra_funds,
+    };
+    TRADES.save(deps.storage, U64Key::new(id), &trade)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "propose_trade")
+        .add_attribute("trade_id", id.to_string())
+        .add_attribute("proposer", trade.proposer)
+        .add_attribute("counterparty", trade.counterparty))
+}
+
+pub fn execute_accept_trade(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    trade_id: u64,
+) -> Result<Response, ContractError> {
+    let trade = TRADES
+        .may_load(deps.storage, U64Key::new(trade_id))?
+        .ok_or(ContractError::NoOffer {})?;
+    // check if sender is the counterparty
+    if info.sender != trade.counterparty {
+        return Err(ContractError::Unauthorized {});
+    }
+    // both sides may have moved their NFTs since the proposal
+    assert_owned_and_approved(deps.as_ref(), &env, &trade.proposer, &trade.offered)?;
+    assert_owned_and_approved(deps.as_ref(), &env, &trade.counterparty, &trade.requested)?;
+    TRADES.remove(deps.storage, U64Key::new(trade_id));
+    // the traded NFTs leave any listing they were on
+    let mut refunds = vec![];
+    for nft in trade.offered.iter().chain(&trade.requested) {
+        refunds.extend(clear_listings(
+            deps.storage,
+            &nft.contract_address,
+            &nft.token_id,
+        )?);
+    }
+
+    let mut res = Response::new()
+        .add_messages(refunds)
+        .add_messages(transfer_nfts(trade.offered, &trade.counterparty)?)
+        .add_messages(transfer_nfts(trade.requested, &trade.proposer)?);
+    if let Some(extra_funds) = trade.extra_funds {
+        res = res
+            .add_message(extra_funds.clone().into_msg(trade.counterparty.clone())?)
+            .add_attribute("extra_funds", extra_funds.to_string());
+    }
+    Ok(res
+        .add_attribute("action", "accept_trade")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("proposer", trade.proposer)
+        .add_attribute("counterparty", trade.counterparty))
+}
+
+pub fn execute_cancel_trade(
+    deps: DepsMut,
+    info: MessageInfo,
+    trade_id: u64,
+) -> Result<Response, ContractError> {
+    let trade = TRADES
+        .may_load(deps.storage, U64Key::new(trade_id))?
+        .ok_or(ContractError::NoOffer {})?;
+    // either party can call the trade off
+    if info.sender != trade.proposer && info.sender != trade.counterparty {
+        return Err(ContractError::Unauthorized {});
+    }
+    TRADES.remove(deps.storage, U64Key::new(trade_id));
+
+    let mut res = Response::new();
+    if let Some(extra_funds) = trade.extra_funds {
+        // Refund the escrowed funds to the proposer.
+        res = res.add_message(extra_funds.into_msg(trade.proposer)?);
+    }
+    Ok(res
+        .add_attribute("action", "cancel_trade")
+        .add_attribute("trade_id", trade_id.to_string())
+        .add_attribute("cancelled_by", info.sender))
+}
+
+pub fn execute_make_offer(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    price: Price,
+    token_id: String,
+    contract_address: String,
+    expiration: Expiration,
+) -> Result<Response, ContractError> {
+    // valid price
+    if price.amount().is_zero() {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps.as_ref(), &price)?;
+    // valid expiration
+    if expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // the token has to exist
+    get_token_owner(deps.as_ref(), token_id.clone(), contract_address.clone())?;
+    let key = (contract_address.clone(), token_id.clone(), bidder.clone());
+    let previous = offers().may_load(deps.storage, key.clone())?;
+    let offer = Offer {
+        bidder: bidder.clone(),
+        contract_address,
+        token_id: token_id.clone(),
+        price: price.clone(),
+        expiration,
+    };
+    offers().save(deps.storage, key, &offer)?;
+
+    let mut res = Response::new()
+        .add_attribute("action", "make_offer")
+        .add_attribute("Bidder", bidder.clone())
+        .add_attribute("NFT", token_id)
+        .add_attribute("price", price.to_string())
+        .add_attribute("expires", expiration.to_string());
+    // a new offer replaces the bidder's previous one, which is refunded
+    if let Some(previous) = previous {
+        res = res
+            .add_message(previous.price.clone().into_msg(bidder)?)
+            .add_attribute("Refund", previous.price.to_string());
+    }
+    Ok(res)
+}
+
+pub fn execute_cancel_offer(
+    deps: DepsMut,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+) -> Result<Response, ContractError> {
+    let key = (contract_address, token_id.clone(), info.sender.to_string());
+    let offer = offers()
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::NoOffer {})?;
+    offers().remove(deps.storage, key)?;
+
+    Ok(Response::new()
+        // Refund the escrowed offer.
+        .add_message(offer.price.clone().into_msg(offer.bidder.clone())?)
+        .add_attribute("action", "cancel_offer")
+        .add_attribute("Bidder", offer.bidder)
+        .add_attribute("NFT", token_id)
+        .add_attribute("Refund", offer.price.to_string()))
+}
+
+// Checks that the sender owns an NFT and can sell it to an offer, returning the seller.
+fn assert_offer_seller(
+    deps: Deps,
+    env: &Env,
+    info: &MessageInfo,
+    token_id: String,
+    contract_address: String,
+) -> Result<String, ContractError> {
+    // bids escrowed in an auction have to be settled through Claim
+    if auction_listings()
+        .key((contract_address.clone(), token_id.clone()))
+        .has(deps.storage)
+    {
+        return Err(ContractError::OngoingAuction {});
+    }
+    // retrieve NFT owner, the marketplace holds escrowed NFTs on the seller's behalf
+    let listing =
+        listings().may_load(deps.storage, (contract_address.clone(), token_id.clone()))?;
+    let token_owner = get_token_owner(deps, token_id, contract_address.clone())?;
+    let owner = match listing {
+        Some(nft) if nft.escrowed => nft.owner,
+        _ => token_owner.clone(),
+    };
+    // check if sender is owner
+    if info.sender != owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    // check if the marketplace contract has approval
+    if token_owner != env.contract.address {
+        let approvals = get_token_approval(deps, contract_address, owner.clone())?;
+        if !marketplace_approved(env, &approvals) {
+            return Err(ContractError::Unapproved {});
+        }
+    }
+    Ok(owner)
+}
+
+pub fn execute_accept_offer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    contract_address: String,
+    bidder: String,
+) -> Result<Response, ContractError> {
+    let key = (contract_address.clone(), token_id.clone(), bidder.clone());
+    let offer = offers()
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::NoOffer {})?;
+    // check expiration
+    if offer.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let owner = assert_offer_seller(
+        deps.as_ref(),
+        &env,
+        &info,
+        token_id.clone(),
+        contract_address.clone(),
+    )?;
+    // the NFT is sold, so neither the offer nor any listing of it remain
+    offers().remove(deps.storage, key)?;
+    let refunds = clear_listings(deps.storage, &contract_address, &token_id)?;
+    let payouts = sale_payouts(
+        deps.as_ref(),
+        offer.price,
+        owner.clone(),
+        token_id.clone(),
+        contract_address.clone(),
+    )?;
+
+    Ok(payouts
+        .add_to(Response::new())
+        .add_messages(refunds)
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_address,
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: bidder.clone(),
+                token_id: token_id.clone(),
+            })?,
+            funds: vec![],
+        }))
+        .add_attribute("Action", "AcceptOffer")
+        .add_attribute("Buyer", bidder)
+        .add_attribute("Seller", owner)
+        .add_attribute("NFT", token_id))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn execute_make_collection_offer(
+    deps: DepsMut,
+    env: Env,
+    bidder: String,
+    payment: Price,
+    collection: String,
+    price: Price,
+    quantity: u64,
+    expiration: Expiration,
+) -> Result<Response, ContractError> {
+    // valid price and quantity
+    if price.amount().is_zero() || quantity == 0 {
+        return Err(ContractError::InvalidAmount {});
+    }
+    // valid denomination
+    assert_accepted_denom(deps.as_ref(), &price)?;
+    // valid expiration
+    if expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    // every unit has to be paid for up front
+    let total = price
+        .amount()
+        .checked_mul(Uint128::from(quantity))
+        .map_err(StdError::from)?;
+    if !payment.same_asset(&price) || payment.amount() != total {
+        return Err(ContractError::InvalidFunds {});
+    }
+    let key = (collection.clone(), bidder.clone());
+    let previous = collection_offers().may_load(deps.storage, key.clone())?;
+    let offer = CollectionOffer {
+        bidder: bidder.clone(),
+        collection: collection.clone(),
+        price: price.clone(),
+        quantity,
+        expiration,
+    };
+    collection_offers().save(deps.storage, key, &offer)?;
+
+    let mut res = Response::new()
+        .add_attribute("action", "make_collection_offer")
+        .add_attribute("Bidder", bidder.clone())
+        .add_attribute("collection", collection)
+        .add_attribute("price", price.to_string())
+        .add_attribute("quantity", quantity.to_string())
+        .add_attribute("expires", expiration.to_string());
+    // a new offer replaces the bidder's previous one, whose remaining escrow is refunded
+    if let Some(previous) = previous {
+        let refund = collection_offer_escrow(&previous)?;
+        res = res
+            .add_message(refund.clone().into_msg(bidder)?)
+            .add_attribute("Refund", refund.to_string());
+    }
+    Ok(res)
+}
+
+// Amount still escrowed for the unfilled units of a collection offer
+fn collection_offer_escrow(offer: &CollectionOffer) -> StdResult<Price> {
+    let amount = offer
+        .price
+        .amount()
+        .checked_mul(Uint128::from(offer.quantity))?;
+    Ok(offer.price.with_amount(amount))
+}
+
+pub fn execute_cancel_collection_offer(
+    deps: DepsMut,
+    info: MessageInfo,
+    collection: String,
+) -> Result<Response, ContractError> {
+    let key = (collection.clone(), info.sender.to_string());
+    let offer = collection_offers()
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::NoOffer {})?;
+    collection_offers().remove(deps.storage, key)?;
+    let refund = collection_offer_escrow(&offer)?;
+
+    Ok(Response::new()
+        // Refund the escrow of the unfilled units.
+        .add_message(refund.clone().into_msg(offer.bidder.clone())?)
+        .add_attribute("action", "cancel_collection_offer")
+        .add_attribute("Bidder", offer.bidder)
+        .add_attribute("collection", collection)
+        .add_attribute("Refund", refund.to_string()))
+}
+
+pub fn execute_accept_collection_offer(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    collection: String,
+    token_id: String,
+    bidder: String,
+) -> Result<Response, ContractError> {
+    let key = (collection.clone(), bidder.clone());
+    let mut offer = collection_offers()
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::NoOffer {})?;
+    // check expiration
+    if offer.expiration.is_expired(&env.block) {
+        return Err(ContractError::Expired {});
+    }
+    let owner = assert_offer_seller(
+        deps.as_ref(),
+        &env,
+        &info,
+        token_id.clone(),
+        collection.clone(),
+    )?;
+    // one unit is filled, the offer is done once none remain
+    offer.quantity -= 1;
+    if offer.quantity == 0 {
+        collection_offers().remove(deps.storage, key)?;
+    } else {
+        collection_offers().save(deps.storage, key, &offer)?;
+    }
+    let refunds = clear_listings(deps.storage, &collection, &token_id)?;
+    let payouts = sale_payouts(
+        deps.as_ref(),
+        offer.price,
+        owner.clone(),
+        token_id.clone(),
+        collection.clone(),
+    )?;
+
+    Ok(payouts
+        .add_to(Response::new())
+        .add_messages(refunds)
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: collection,
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: bidder.clone(),
+                token_id: token_id.clone(),
+            })?,
+            funds: vec![],
+        }))
+        .add_attribute("Action", "AcceptCollectionOffer")
+        .add_attribute("Buyer", bidder)
+        .add_attribute("Seller", owner)
+        .add_attribute("NFT", token_id)
+        .add_attribute("Remaining", offer.quantity.to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    // contracts deployed before the config existed get one from the migrate message
+    if CONFIG.may_load(deps.storage)?.is_none() {
+        let owner = msg.owner.ok_or(ContractError::MissingConfig {})?;
+        let owner = deps.api.addr_validate(&owner)?;
+        let config = Config {
+            owner: owner.clone(),
+            accepted_denoms: msg
+                .accepted_denoms
+                .unwrap_or_else(|| vec!["uusd".to_string()]),
+            accepted_cw20s: vec![],
+            // earlier versions took no fee
+            fee_bps: 0,
+            fee_collector: owner,
+            auction_extension: AuctionExtension::default(),
+            cancel_penalty: CancelPenalty::default(),
+        };
+        CONFIG.save(deps.storage, &config)?;
+    }
+    // re-key listings stored by token_id alone under (contract_address, token_id)
+    let legacy_list = LEGACY_LIST
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, legacy_nft) in legacy_list.iter() {
+        let nft = NFT {
+            token_id: legacy_nft.token_id.clone(),
+            owner: legacy_nft.owner.clone(),
+            contract_address: legacy_nft.contract_address.clone(),
+            price: Price::Native(legacy_nft.price.clone()),
+            expiration: legacy_nft.expiration,
+            escrowed: false,
+            dutch_auction: None,
+            reserved_for: None,
+        };
+        listings().save(
+            deps.storage,
+            (nft.contract_address.clone(), nft.token_id.clone()),
+            &nft,
+        )?;
+        LEGACY_LIST.remove(deps.storage, nft.token_id);
+    }
+    let legacy_auction_list = LEGACY_AUCTION_LIST
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, legacy_nft) in legacy_auction_list.iter() {
+        let token_id = legacy_nft.token_id.clone();
+        let new_key = (legacy_nft.contract_address.clone(), token_id.clone());
+        let nft = AuctionNft {
+            token_id: token_id.clone(),
+            owner: legacy_nft.owner.clone(),
+            contract_address: legacy_nft.contract_address.clone(),
+            starting_price: Price::Native(legacy_nft.starting_price.clone()),
+            min_bid_increment: None,
+            expiration: legacy_nft.expiration,
+            escrowed: false,
+            reserve_price: None,
+            buy_now_price: None,
+            auction_id: next_auction_id(deps.storage)?,
+        };
+        auction_listings().save(deps.storage, new_key.clone(), &nft)?;
+        // the bidder map never stored the collection, take it from the matching auction
+        if let Some(bidder) = LEGACY_HIGHEST_BIDDER.may_load(deps.storage, token_id.clone())? {
+            let bidder = Bidder {
+                address: bidder.address,
+                bid: Price::Native(bidder.bid),
+            };
+            HIGHEST_BIDDER.save(deps.storage, new_key, &bidder)?;
+            LEGACY_HIGHEST_BIDDER.remove(deps.storage, token_id.clone());
+        }
+        LEGACY_AUCTION_LIST.remove(deps.storage, token_id);
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("listings", legacy_list.len().to_string())
+        .add_attribute("auction_listings", legacy_auction_list.len().to_string()))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::GetRoyalty {
+            token_id,
+            contract_address,
+            sale_price,
+        } => to_binary(&query_royalty(
+            deps,
+            token_id,
+            contract_address,
+            sale_price,
+        )?),
+        QueryMsg::GetListing {
+            token_id,
+            contract_address,
+        } => to_binary(&query_listing(deps, token_id, contract_address)?),
+        QueryMsg::GetCurrentPrice {
+            token_id,
+            contract_address,
+        } => to_binary(&query_current_price(deps, env, token_id, contract_address)?),
+        QueryMsg::GetListings {
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_listings(
+            deps,
+            env,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::GetAuctionListing {
+            token_id,
+            contract_address,
+        } => to_binary(&query_auction_listing(deps, token_id, contract_address)?),
+        QueryMsg::GetAuctionListings {
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_auction_listings(
+            deps,
+            env,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::ListingsBySeller {
+            seller,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_listings_by_seller(
+            deps,
+            env,
+            seller,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::ListingsByCollection {
+            contract_address,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_listings_by_collection(
+            deps,
+            env,
+            contract_address,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::ListingsByPriceRange {
+            denom,
+            min,
+            max,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_listings_by_price_range(
+            deps,
+            env,
+            denom,
+            min,
+            max,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::AuctionListingsBySeller {
+            seller,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_auction_listings_by_seller(
+            deps,
+            env,
+            seller,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::AuctionListingsByCollection {
+            contract_address,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_auction_listings_by_collection(
+            deps,
+            env,
+            contract_address,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::AuctionListingsByPriceRange {
+            denom,
+            min,
+            max,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_auction_listings_by_price_range(
+            deps,
+            env,
+            denom,
+            min,
+            max,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::OffersByToken {
+            token_id,
+            contract_address,
+            start_after,
+            limit,
+        } => to_binary(&query_offers_by_token(
+            deps,
+            token_id,
+            contract_address,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::OffersByBidder {
+            bidder,
+            start_after,
+            limit,
+        } => to_binary(&query_offers_by_bidder(deps, bidder, start_after, limit)?),
+        QueryMsg::GetCollectionOffer { collection, bidder } => {
+            to_binary(&query_collection_offer(deps, collection, bidder)?)
+        }
+        QueryMsg::CollectionOffers {
+            collection,
+            denom,
+            start_after,
+            include_expired,
+            limit,
+        } => to_binary(&query_collection_offers(
+            deps,
+            env,
+            collection,
+            denom,
+            start_after,
+            include_expired,
+            limit,
+        )?),
+        QueryMsg::GetTrade { trade_id } => to_binary(&query_trade(deps, trade_id)?),
+        QueryMsg::GetTrades { start_after, limit } => {
+            to_binary(&query_trades(deps, start_after, limit)?)
+        }
+        QueryMsg::GetBundle { bundle_id } => to_binary(&query_bundle(deps, bundle_id)?),
+        QueryMsg::GetBundles { start_after, limit } => {
+            to_binary(&query_bundles(deps, start_after, limit)?)
+        }
+        QueryMsg::GetBids {
+            auction_id,
+            start_after,
+            limit,
+        } => to_binary(&query_bids(deps, auction_id, start_after, limit)?),
+        QueryMsg::GetSealedAuction {
+            token_id,
+            contract_address,
+        } => to_binary(&query_sealed_auction(deps, token_id, contract_address)?),
+        QueryMsg::GetSealedBids {
+            token_id,
+            contract_address,
+            start_after,
+            limit,
+        } => to_binary(&query_sealed_bids(
+            deps,
+            token_id,
+            contract_address,
+            start_after,
+            limit,
+        )?),
+        QueryMsg::GetHighestBidder {
+            token_id,
+            contract_address,
+        } => to_binary(&query_highest_bidder(deps, token_id, contract_address)?),
+    }
+}
+fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ConfigResponse { config })
+}
+fn query_royalty(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+    sale_price: Price,
+) -> StdResult<RoyaltyResponse> {
+    let royalty = get_royalty(deps, token_id, contract_address, &sale_price)?;
+    Ok(match royalty {
+        Some((recipient, amount)) => RoyaltyResponse {
+            recipient: Some(recipient),
+            amount,
+        },
+        None => RoyaltyResponse {
+            recipient: None,
+            amount: sale_price.with_amount(Uint128::zero()),
+        },
+    })
+}
+fn query_listing(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+) -> StdResult<ListingResponse> {
+    let nft = listings().load(deps.storage, (contract_address, token_id))?;
+    Ok(ListingResponse { nft })
+}
+fn query_current_price(
+    deps: Deps,
+    env: Env,
+    token_id: String,
+    contract_address: String,
+) -> StdResult<CurrentPriceResponse> {
+    let nft = listings().load(deps.storage, (contract_address, token_id))?;
+    Ok(CurrentPriceResponse {
+        price: nft.current_price(&env.block),
+    })
+}
+fn query_listings(
+    deps: Deps,
+    env: Env,
+    start_after: Option<(String, String)>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<ListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after.map(|key| Bound::exclusive(key.joined_key()));
+    let nfts = listings()
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, nft)| nft))
+        .filter(|nft| {
+            include_expired
+                || nft
+                    .as_ref()
+                    .map_or(true, |nft| !nft.expiration.is_expired(&env.block))
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(ListingsResponse { nfts })
+}
+fn query_auction_listing(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+) -> StdResult<AuctionListingResponse> {
+    let auction_nft = auction_listings()
+        .load(deps.storage, (contract_address, token_id))?
+        .redact_reserve();
+    Ok(AuctionListingResponse { auction_nft })
+}
+fn query_auction_listings(
+    deps: Deps,
+    env: Env,
+    start_after: Option<(String, String)>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<AuctionListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after.map(|key| Bound::exclusive(key.joined_key()));
+    let auction_nfts = auction_listings()
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, auction_nft)| auction_nft.redact_reserve()))
+        .filter(|auction_nft| {
+            include_expired
+                || auction_nft.as_ref().map_or(true, |auction_nft| {
+                    !auction_nft.expiration.is_expired(&env.block)
+                })
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(AuctionListingsResponse { auction_nfts })
+}
+// Bounds for ranging over a price index within one denom, in ascending price order. Paging
+// resumes after the (price, key) entry given as start_after.
+fn price_range_bounds(
+    min: Option<Uint128>,
+    max: Option<Uint128>,
+    start_after: Option<(Uint128, (String, String))>,
+) -> (Option<Bound>, Option<Bound>) {
+    let start = match start_after {
+        Some((price, key)) => Some(Bound::exclusive(
+            (U128Key::new(price.u128()), key.joined_key()).joined_key(),
+        )),
+        None => min.map(|min| Bound::inclusive((U128Key::new(min.u128()), vec![]).joined_key())),
+    };
+    // everything priced below max + 1 is at most max
+    let end = max
+        .and_then(|max| max.u128().checked_add(1))
+        .map(|end| Bound::exclusive((U128Key::new(end), vec![]).joined_key()));
+    (start, end)
+}
+fn query_listings_by_seller(
+    deps: Deps,
+    env: Env,
+    seller: String,
+    start_after: Option<(String, String)>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<ListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after.map(|key| Bound::exclusive(key.joined_key()));
+    let nfts = listings()
+        .idx
+        .seller
+        .prefix(seller.into_bytes())
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, nft)| nft))
+        .filter(|nft| {
+            include_expired
+                || nft
+                    .as_ref()
+                    .map_or(true, |nft| !nft.expiration.is_expired(&env.block))
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(ListingsResponse { nfts })
+}
+fn query_listings_by_collection(
+    deps: Deps,
+    env: Env,
+    contract_address: String,
+    start_after: Option<String>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<ListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after
+        .map(|token_id| Bound::exclusive((contract_address.clone(), token_id).joined_key()));
+    let nfts = listings()
+        .idx
+        .collection
+        .prefix(contract_address.into_bytes())
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, nft)| nft))
+        .filter(|nft| {
+            include_expired
+                || nft
+                    .as_ref()
+                    .map_or(true, |nft| !nft.expiration.is_expired(&env.block))
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(ListingsResponse { nfts })
+}
+#[allow(clippy::too_many_arguments)]
+fn query_listings_by_price_range(
+    deps: Deps,
+    env: Env,
+    denom: String,
+    min: Option<Uint128>,
+    max: Option<Uint128>,
+    start_after: Option<(Uint128, (String, String))>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<ListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let (start, end) = price_range_bounds(min, max, start_after);
+    let nfts = listings()
+        .idx
+        .price
+        .sub_prefix(denom.into_bytes())
+        .range(deps.storage, start, end, Order::Ascending)
+        .map(|item| item.map(|(_, nft)| nft))
+        .filter(|nft| {
+            nft.as_ref().map_or(true, |nft| {
+                nft.dutch_auction.is_none()
+                    && (include_expired || !nft.expiration.is_expired(&env.block))
+            })
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(ListingsResponse { nfts })
+}
+fn query_auction_listings_by_seller(
+    deps: Deps,
+    env: Env,
+    seller: String,
+    start_after: Option<(String, String)>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<AuctionListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after.map(|key| Bound::exclusive(key.joined_key()));
+    let auction_nfts = auction_listings()
+        .idx
+        .seller
+        .prefix(seller.into_bytes())
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, auction_nft)| auction_nft.redact_reserve()))
+        .filter(|auction_nft| {
+            include_expired
+                || auction_nft.as_ref().map_or(true, |auction_nft| {
+                    !auction_nft.expiration.is_expired(&env.block)
+                })
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(AuctionListingsResponse { auction_nfts })
+}
+fn query_auction_listings_by_collection(
+    deps: Deps,
+    env: Env,
+    contract_address: String,
+    start_after: Option<String>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<AuctionListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let start = start_after
+        .map(|token_id| Bound::exclusive((contract_address.clone(), token_id).joined_key()));
+    let auction_nfts = auction_listings()
+        .idx
+        .collection
+        .prefix(contract_address.into_bytes())
+        .range(deps.storage, start, None, Order::Ascending)
+        .map(|item| item.map(|(_, auction_nft)| auction_nft.redact_reserve()))
+        .filter(|auction_nft| {
+            include_expired
+                || auction_nft.as_ref().map_or(true, |auction_nft| {
+                    !auction_nft.expiration.is_expired(&env.block)
+                })
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(AuctionListingsResponse { auction_nfts })
+}
+#[allow(clippy::too_many_arguments)]
+fn query_auction_listings_by_price_range(
+    deps: Deps,
+    env: Env,
+    denom: String,
+    min: Option<Uint128>,
+    max: Option<Uint128>,
+    start_after: Option<(Uint128, (String, String))>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<AuctionListingsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    let (start, end) = price_range_bounds(min, max, start_after);
+    let auction_nfts = auction_listings()
+        .idx
+        .price
+        .sub_prefix(denom.into_bytes())
+        .range(deps.storage, start, end, Order::Ascending)
+        .map(|item| item.map(|(_, auction_nft)| auction_nft.redact_reserve()))
+        .filter(|auction_nft| {
+            include_expired
+                || auction_nft.as_ref().map_or(true, |auction_nft| {
+                    !auction_nft.expiration.is_expired(&env.block)
+                })
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(AuctionListingsResponse { auction_nfts })
+}
+fn query_highest_bidder(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+) -> StdResult<HighestBidderResponse> {
+    let highest_bidder = HIGHEST_BIDDER.load(deps.storage, (contract_address, token_id))?;
+    Ok(HighestBidderResponse {
+        bidder: highest_bidder,
+    })
+}
+fn query_trade(deps: Deps, trade_id: u64) -> StdResult<TradeResponse> {
+    let trade = TRADES.load(deps.storage, U64Key::new(trade_id))?;
+    Ok(TradeResponse { trade })
+}
+fn query_trades(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<TradesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::new(id)));
+    let trades = TRADES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, trade)| trade))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(TradesResponse { trades })
+}
+fn query_bundle(deps: Deps, bundle_id: u64) -> StdResult<BundleResponse> {
+    let bundle = BUNDLES.load(deps.storage, U64Key::new(bundle_id))?;
+    let highest_bid = BUNDLE_BIDS.may_load(deps.storage, U64Key::new(bundle_id))?;
+    Ok(BundleResponse {
+        bundle,
+        highest_bid,
+    })
+}
+fn query_bundles(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<BundlesResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::new(id)));
+    let bundles = BUNDLES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, bundle)| bundle))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(BundlesResponse { bundles })
+}
+fn query_bids(
+    deps: Deps,
+    auction_id: u64,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<BidsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::new(id)));
+    let bids = BIDS
+        .prefix(U64Key::new(auction_id))
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, bid)| bid))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(BidsResponse { bids })
+}
+fn query_sealed_auction(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+) -> StdResult<SealedAuctionResponse> {
+    let auction = SEALED_AUCTIONS.load(deps.storage, (contract_address, token_id))?;
+    Ok(SealedAuctionResponse { auction })
+}
+fn query_sealed_bids(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<SealedBidsResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let bids = SEALED_BIDS
+        .prefix((contract_address, token_id))
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, bid)| bid))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(SealedBidsResponse { bids })
+}
+fn query_offers_by_token(
+    deps: Deps,
+    token_id: String,
+    contract_address: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let offers = offers()
+        .prefix((contract_address, token_id))
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, offer)| offer))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(OffersResponse { offers })
+}
+fn query_offers_by_bidder(
+    deps: Deps,
+    bidder: String,
+    start_after: Option<(String, String)>,
+    limit: Option<u32>,
+) -> StdResult<OffersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(|(contract_address, token_id)| {
+        Bound::exclusive((contract_address, token_id, bidder.clone()).joined_key())
+    });
+    let offers = offers()
+        .idx
+        .bidder
+        .prefix(bidder.into_bytes())
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, offer)| offer))
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(OffersResponse { offers })
+}
+fn query_collection_offer(
+    deps: Deps,
+    collection: String,
+    bidder: String,
+) -> StdResult<CollectionOfferResponse> {
+    let offer = collection_offers().may_load(deps.storage, (collection, bidder))?;
+    Ok(CollectionOfferResponse { offer })
+}
+fn query_collection_offers(
+    deps: Deps,
+    env: Env,
+    collection: String,
+    denom: String,
+    start_after: Option<(Uint128, String)>,
+    include_expired: Option<bool>,
+    limit: Option<u32>,
+) -> StdResult<CollectionOffersResponse> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let include_expired = include_expired.unwrap_or(false);
+    // best offers first, so paging resumes below the (price, key) of the last offer returned
+    let end = start_after.map(|(price, bidder)| {
+        Bound::exclusive(
+            (
+                U128Key::new(price.u128()),
+                (collection.clone(), bidder).joined_key(),
+            )
+                .joined_key(),
+        )
+    });
+    let offers = collection_offers()
+        .idx
+        .price
+        .sub_prefix((collection.into_bytes(), denom.into_bytes()))
+        .range(deps.storage, None, end, Order::Descending)
+        .map(|item| item.map(|(_, offer)| offer))
+        .filter(|offer| {
+            include_expired
+                || offer
+                    .as_ref()
+                    .map_or(true, |offer| !offer.expiration.is_expired(&env.block))
+        })
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+    Ok(CollectionOffersResponse { offers })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{LegacyAuctionNft, LegacyBidder, LegacyNft};
+    use cosmwasm_std::testing::{
+        mock_env, mock_info, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        coin, from_slice, ContractResult, Empty, OwnedDeps, Querier, QuerierResult, SystemError,
+        SystemResult,
+    };
+
+    const COLLECTION: &str = "collection";
+    const SELLER: &str = "seller";
+    const COLLECTOR: &str = "collector";
+    const CREATOR: &str = "creator";
+
+    // Answers the cw721 and cw2981 queries the marketplace sends to mock collections
+    struct MarketQuerier {
+        base: MockQuerier,
+        /// Owner of each (collection, token_id)
+        owners: BTreeMap<(String, String), String>,
+        /// Owners that have not approved the marketplace as operator
+        unapproved: Vec<String>,
+        /// Expiry of the approvals of owners whose approval doesn't last forever
+        approval_expiries: BTreeMap<String, Expiration>,
+        /// cw2981 royalty recipient and basis points of each collection
+        royalties: BTreeMap<String, (String, u64)>,
+    }
+
+    impl Querier for MarketQuerier {
+        fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+            let request: QueryRequest<Empty> = match from_slice(bin_request) {
+                Ok(request) => request,
+                Err(err) => {
+                    return SystemResult::Err(SystemError::InvalidRequest {
+                        error: err.to_string(),
+                        request: bin_request.into(),
+                    })
+                }
+            };
+            match request {
+                QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
+                    SystemResult::Ok(self.query_collection(contract_addr, msg))
+                }
+                _ => self.base.raw_query(bin_request),
+            }
+        }
+    }
+
+    impl MarketQuerier {
+        fn query_collection(&self, collection: String, msg: Binary) -> ContractResult<Binary> {
+            if let Ok(msg) = from_binary::<Cw721QueryMsg>(&msg) {
+                return match msg {
+                    Cw721QueryMsg::OwnerOf { token_id, .. } => {
+                        match self.owners.get(&(collection, token_id)) {
+                            Some(owner) => to_binary(&OwnerOfResponse {
+                                owner: owner.clone(),
+                                approvals: vec![],
+                            })
+                            .into(),
+                            None => ContractResult::Err("token not found".to_string()),
+                        }
+                    }
+                    Cw721QueryMsg::ApprovedForAll { owner, .. } => {
+                        let operators = if self.unapproved.contains(&owner) {
+                            vec![]
+                        } else {
+                            vec![Approval {
+                                spender: MOCK_CONTRACT_ADDR.to_string(),
+                                expires: self
+                                    .approval_expiries
+                                    .get(&owner)
+                                    .cloned()
+                                    .unwrap_or(Expiration::Never {}),
+                            }]
+                        };
+                        to_binary(&ApprovedForAllResponse { operators }).into()
+                    }
+                    _ => ContractResult::Err("unsupported query".to_string()),
+                };
+            }
+            let Cw2981QueryMsg::Extension {
+                msg: Cw2981ExtensionMsg::RoyaltyInfo { sale_price, .. },
+            } = match from_binary(&msg) {
+                Ok(msg) => msg,
+                Err(err) => return ContractResult::Err(err.to_string()),
+            };
+            match self.royalties.get(&collection) {
+                Some((address, bps)) => to_binary(&RoyaltiesInfoResponse {
+                    address: address.clone(),
+                    royalty_amount: sale_price.multiply_ratio(*bps, MAX_BPS),
+                })
+                .into(),
+                None => ContractResult::Err("cw2981 not implemented".to_string()),
+            }
+        }
+    }
+
+    type MarketDeps = OwnedDeps<MockStorage, MockApi, MarketQuerier>;
+
+    // A marketplace taking fee_bps on sales of uusd and the "token" cw20
+    fn mock_deps() -> MarketDeps {
+        OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: MarketQuerier {
+                base: MockQuerier::new(&[]),
+                owners: BTreeMap::new(),
+                unapproved: vec![],
+                approval_expiries: BTreeMap::new(),
+                royalties: BTreeMap::new(),
+            },
+        }
+    }
+
+    fn setup(fee_bps: u64) -> MarketDeps {
+        let mut deps = mock_deps();
+        let msg = InstantiateMsg {
+            owner: None,
+            accepted_denoms: vec!["uluna".to_string(), "uusd".to_string()],
+            accepted_cw20s: vec!["token".to_string()],
+            fee_bps,
+            fee_collector: Some(COLLECTOR.to_string()),
+            auction_extension: None,
+            cancel_penalty: None,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+        deps
+    }
+
+    fn mint(deps: &mut MarketDeps, token_id: &str, owner: &str) {
+        mint_in(deps, COLLECTION, token_id, owner);
+    }
+
+    fn mint_in(deps: &mut MarketDeps, collection: &str, token_id: &str, owner: &str) {
+        deps.querier.owners.insert(
+            (collection.to_string(), token_id.to_string()),
+            owner.to_string(),
+        );
+    }
+
+    fn uusd(amount: u128) -> Price {
+        Price::Native(coin(amount, "uusd"))
+    }
+
+    fn bank_send(recipient: &str, amount: u128) -> CosmosMsg {
+        CosmosMsg::Bank(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![coin(amount, "uusd")],
+        })
+    }
+
+    fn seller_payouts(deps: &MarketDeps, price: Price) -> SalePayouts {
+        sale_payouts(
+            deps.as_ref(),
+            price,
+            SELLER.to_string(),
+            "1".to_string(),
+            COLLECTION.to_string(),
+        )
+        .unwrap()
+    }
+
+    fn transfer_nft(token_id: &str, recipient: &str) -> CosmosMsg {
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: COLLECTION.to_string(),
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                recipient: recipient.to_string(),
+                token_id: token_id.to_string(),
+            })
+            .unwrap(),
+            funds: vec![],
+        })
+    }
+
+    fn sell(deps: &mut MarketDeps, token_id: &str, price: Price) {
+        mint(deps, token_id, SELLER);
+        let msg = ExecuteMsg::Sell {
+            token_id: token_id.to_string(),
+            contract_address: COLLECTION.to_string(),
+            price,
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    // Auctions token_id for 10 blocks
+    fn auction(
+        deps: &mut MarketDeps,
+        token_id: &str,
+        starting_price: Price,
+        min_bid_increment: Option<BidIncrement>,
+    ) {
+        mint(deps, token_id, SELLER);
+        let msg = ExecuteMsg::Auction {
+            token_id: token_id.to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price,
+            min_bid_increment,
+            expiration: Expiration::AtHeight(mock_env().block.height + 10),
+            reserve_price: None,
+            buy_now_price: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    fn bid(deps: &mut MarketDeps, bidder: &str, funds: Coin) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::Bid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(bidder, &[funds]), msg)
+    }
+
+    fn sent_messages(res: &Response) -> Vec<CosmosMsg> {
+        res.messages
+            .iter()
+            .map(|sub_msg| sub_msg.msg.clone())
+            .collect()
+    }
+
+    #[test]
+    fn proper_initialization() {
+        let deps = setup(250);
+        let config = query_config(deps.as_ref()).unwrap().config;
+        assert_eq!(config.owner, "admin");
+        assert_eq!(config.fee_bps, 250);
+        assert_eq!(config.fee_collector, COLLECTOR);
+
+        let mut deps = setup(0);
+        let msg = InstantiateMsg {
+            owner: None,
+            accepted_denoms: vec![],
+            accepted_cw20s: vec![],
+            fee_bps: MAX_BPS + 1,
+            fee_collector: None,
+            auction_extension: None,
+            cancel_penalty: None,
+        };
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("admin", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidFee {});
+
+        let msg = InstantiateMsg {
+            fee_bps: 0,
+            cancel_penalty: Some(CancelPenalty::BasisPoints(MAX_BPS + 1)),
+            ..msg
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidFee {});
+    }
+
+    #[test]
+    fn proper_sell() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let msg = ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        // sender has to be owner of NFT
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("anyone", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        let nft = query_listing(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+            .unwrap()
+            .nft;
+        assert_eq!(nft.owner, SELLER);
+        assert_eq!(nft.price, uusd(100));
+    }
+
+    #[test]
+    fn auction_extension_never_overflows() {
+        let env = mock_env();
+        let ext = AuctionExtension {
+            window: u64::MAX,
+            extension: u64::MAX,
+        };
+        assert_eq!(
+            ext.extend(&Expiration::AtHeight(u64::MAX), &env.block),
+            None
+        );
+        assert_eq!(
+            ext.extend(&Expiration::AtTime(env.block.time), &env.block),
+            None
+        );
+
+        let ext = AuctionExtension {
+            window: 10,
+            extension: 5,
+        };
+        assert_eq!(
+            ext.extend(&Expiration::AtHeight(env.block.height + 3), &env.block),
+            Some(Expiration::AtHeight(env.block.height + 8))
+        );
+        assert_eq!(
+            ext.extend(&Expiration::AtTime(env.block.time), &env.block),
+            Some(Expiration::AtTime(env.block.time.plus_seconds(5)))
+        );
+    }
+
+    #[test]
+    fn listed_nft_cannot_be_auctioned() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let msg = ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Auction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::Never {},
+            reserve_price: None,
+            buy_now_price: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyListed {});
+
+        // nor sent into escrow for an auction
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: SELLER.to_string(),
+            token_id: "1".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                starting_price: uusd(100),
+                min_bid_increment: None,
+                expiration: Expiration::Never {},
+                reserve_price: None,
+                buy_now_price: None,
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info(COLLECTION, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyListed {});
+    }
+
+    #[test]
+    fn sale_payouts_split_fee_royalty_and_seller() {
+        let mut deps = setup(250);
+        execute_set_royalty(
+            deps.as_mut(),
+            mock_info("admin", &[]),
+            COLLECTION.to_string(),
+            CREATOR.to_string(),
+            500,
+        )
+        .unwrap();
+
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.fee, uusd(25));
+        assert_eq!(payouts.royalty, uusd(50));
+        assert_eq!(payouts.royalty_recipient, Some(CREATOR.to_string()));
+        assert_eq!(payouts.seller_amount, uusd(925));
+        assert_eq!(
+            payouts.messages,
+            vec![
+                bank_send(COLLECTOR, 25),
+                bank_send(CREATOR, 50),
+                bank_send(SELLER, 925),
+            ]
+        );
+    }
+
+    #[test]
+    fn sale_payouts_prefer_cw2981_over_registry() {
+        let mut deps = setup(0);
+        execute_set_royalty(
+            deps.as_mut(),
+            mock_info("admin", &[]),
+            COLLECTION.to_string(),
+            CREATOR.to_string(),
+            500,
+        )
+        .unwrap();
+        deps.querier
+            .royalties
+            .insert(COLLECTION.to_string(), ("artist".to_string(), 1_000));
+
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.royalty, uusd(100));
+        assert_eq!(payouts.royalty_recipient, Some("artist".to_string()));
+        assert_eq!(
+            payouts.messages,
+            vec![bank_send("artist", 100), bank_send(SELLER, 900)]
+        );
+    }
+
+    #[test]
+    fn sale_payouts_cap_royalty() {
+        // a cw2981 royalty above the price only takes what is left after the fee
+        let mut deps = setup(1_000);
+        deps.querier
+            .royalties
+            .insert(COLLECTION.to_string(), ("artist".to_string(), 2 * MAX_BPS));
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.fee, uusd(100));
+        assert_eq!(payouts.royalty, uusd(900));
+        assert_eq!(payouts.seller_amount, uusd(0));
+        assert_eq!(
+            payouts.messages,
+            vec![bank_send(COLLECTOR, 100), bank_send("artist", 900)]
+        );
+
+        // an invalid cw2981 recipient is paid nothing
+        deps.querier
+            .royalties
+            .insert(COLLECTION.to_string(), ("a".to_string(), 500));
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.royalty_recipient, None);
+        assert_eq!(payouts.seller_amount, uusd(900));
+    }
+
+    #[test]
+    fn sale_payouts_skip_zero_amounts() {
+        // without fee or royalty everything goes to the seller
+        let deps = setup(0);
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.fee, uusd(0));
+        assert_eq!(payouts.royalty, uusd(0));
+        assert_eq!(payouts.royalty_recipient, None);
+        assert_eq!(payouts.messages, vec![bank_send(SELLER, 1_000)]);
+
+        // a fee rounding down to nothing and a full fee leave out the empty shares
+        let deps = setup(1);
+        let payouts = seller_payouts(&deps, uusd(99));
+        assert_eq!(payouts.fee, uusd(0));
+        assert_eq!(payouts.messages, vec![bank_send(SELLER, 99)]);
+
+        let deps = setup(MAX_BPS);
+        let payouts = seller_payouts(&deps, uusd(1_000));
+        assert_eq!(payouts.seller_amount, uusd(0));
+        assert_eq!(payouts.messages, vec![bank_send(COLLECTOR, 1_000)]);
+    }
+
+    #[test]
+    fn sale_payouts_in_cw20() {
+        let deps = setup(1_000);
+        let price = Price::Cw20(Cw20Coin {
+            address: "token".to_string(),
+            amount: Uint128::new(1_000),
+        });
+        let payouts = seller_payouts(&deps, price.clone());
+        assert_eq!(
+            payouts.transfers,
+            vec![
+                (COLLECTOR.to_string(), price.with_amount(Uint128::new(100))),
+                (SELLER.to_string(), price.with_amount(Uint128::new(900))),
+            ]
+        );
+        assert_eq!(
+            payouts.messages[1],
+            price
+                .with_amount(Uint128::new(900))
+                .into_msg(SELLER.to_string())
+                .unwrap()
+        );
+    }
+
+    fn commitment(bidder: &str, amount: u128) -> String {
+        hex::encode(Sha256::digest(
+            format!("{}:{}:salt", bidder, amount).as_bytes(),
+        ))
+    }
+
+    // Opens a sealed auction on token "1" with a minimum price of 100, committing bids for
+    // 100 seconds and revealing them for the next 100
+    fn start_sealed_auction(deps: &mut MarketDeps, pricing: SealedBidPricing) {
+        mint(deps, "1", SELLER);
+        let env = mock_env();
+        let msg = ExecuteMsg::SealedAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            min_price: uusd(100),
+            commit_end: env.block.time.plus_seconds(100),
+            reveal_end: env.block.time.plus_seconds(200),
+            pricing,
+        };
+        execute(deps.as_mut(), env, mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    fn commit_bid(deps: &mut MarketDeps, bidder: &str, deposit: u128, amount: u128) {
+        let msg = ExecuteMsg::CommitBid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            commitment: commitment(bidder, amount),
+        };
+        let info = mock_info(bidder, &[coin(deposit, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    fn reveal_bid(deps: &mut MarketDeps, bidder: &str, amount: u128) {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(150);
+        let msg = ExecuteMsg::RevealBid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            amount: Uint128::new(amount),
+            salt: "salt".to_string(),
+        };
+        execute(deps.as_mut(), env, mock_info(bidder, &[]), msg).unwrap();
+    }
+
+    fn settle_sealed_auction(deps: &mut MarketDeps) -> Response {
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(200);
+        let msg = ExecuteMsg::SettleSealedAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap()
+    }
+
+    #[test]
+    fn sealed_auction_tie_goes_to_earlier_reveal() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        commit_bid(&mut deps, "alice", 300, 200);
+        commit_bid(&mut deps, "bob", 200, 200);
+        reveal_bid(&mut deps, "bob", 200);
+        reveal_bid(&mut deps, "alice", 200);
+
+        let res = settle_sealed_auction(&mut deps);
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send("alice", 300),
+                bank_send(SELLER, 200),
+                transfer_nft("1", "bob"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sealed_auction_second_price() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::SecondPrice);
+        commit_bid(&mut deps, "alice", 300, 300);
+        commit_bid(&mut deps, "bob", 250, 250);
+        commit_bid(&mut deps, "carol", 200, 150);
+        reveal_bid(&mut deps, "alice", 300);
+        reveal_bid(&mut deps, "bob", 250);
+        reveal_bid(&mut deps, "carol", 150);
+
+        // the winner pays the second highest bid and gets the rest of the deposit back
+        let res = settle_sealed_auction(&mut deps);
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send("alice", 50),
+                bank_send("bob", 250),
+                bank_send("carol", 200),
+                bank_send(SELLER, 250),
+                transfer_nft("1", "alice"),
+            ]
+        );
+    }
+
+    #[test]
+    fn sealed_auction_forfeits_unrevealed_and_refunds_below_minimum() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        commit_bid(&mut deps, "alice", 200, 200);
+        commit_bid(&mut deps, "bob", 500, 400);
+        commit_bid(&mut deps, "carol", 100, 50);
+        reveal_bid(&mut deps, "alice", 200);
+        reveal_bid(&mut deps, "carol", 50);
+
+        let res = settle_sealed_auction(&mut deps);
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send("carol", 100),
+                bank_send(SELLER, 500),
+                bank_send(SELLER, 200),
+                transfer_nft("1", "alice"),
+            ]
+        );
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "Forfeited" && attr.value == "500"));
+    }
+
+    #[test]
+    fn sealed_auction_refunds_every_deposit_when_nft_moved() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        // the NFT can't be listed elsewhere while it is auctioned
+        let msg = ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyListed {});
+
+        commit_bid(&mut deps, "alice", 200, 200);
+        commit_bid(&mut deps, "bob", 300, 300);
+        reveal_bid(&mut deps, "alice", 200);
+        mint(&mut deps, "1", "someone");
+
+        let res = settle_sealed_auction(&mut deps);
+        assert_eq!(
+            sent_messages(&res),
+            vec![bank_send("alice", 200), bank_send("bob", 300)]
+        );
+    }
+
+    #[test]
+    fn claim_refunds_bid_when_nft_moved() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let env = mock_env();
+        let msg = ExecuteMsg::Auction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::AtHeight(env.block.height + 10),
+            reserve_price: None,
+            buy_now_price: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info(SELLER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::Bid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("bidder", &[coin(150, "uusd")]);
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        mint(&mut deps, "1", "someone");
+
+        let mut env = env;
+        env.block.height += 11;
+        let msg = ExecuteMsg::Claim {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 150)]);
+        assert!(auction_listings()
+            .may_load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap()
+            .is_none());
+    }
+
+    fn batch_buy(token_ids: &[&str], best_effort: bool) -> ExecuteMsg {
+        ExecuteMsg::BatchBuy {
+            items: token_ids
+                .iter()
+                .map(|token_id| ListingKey {
+                    token_id: token_id.to_string(),
+                    contract_address: COLLECTION.to_string(),
+                })
+                .collect(),
+            best_effort: Some(best_effort),
+        }
+    }
+
+    #[test]
+    fn merge_transfers_per_recipient_and_asset() {
+        let token = |amount: u128| {
+            Price::Cw20(Cw20Coin {
+                address: "token".to_string(),
+                amount: Uint128::new(amount),
+            })
+        };
+        let luna = |amount: u128| Price::Native(coin(amount, "uluna"));
+        let messages = merge_transfers(vec![
+            ("alice".to_string(), uusd(10)),
+            ("bob".to_string(), uusd(5)),
+            ("alice".to_string(), luna(3)),
+            ("alice".to_string(), uusd(7)),
+            ("alice".to_string(), token(4)),
+            ("alice".to_string(), token(6)),
+            ("bob".to_string(), uusd(0)),
+            ("carol".to_string(), uusd(0)),
+            ("carol".to_string(), token(0)),
+        ])
+        .unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "alice".to_string(),
+                    amount: vec![coin(3, "uluna"), coin(17, "uusd")],
+                }),
+                bank_send("bob", 5),
+                token(10).into_msg("alice".to_string()).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_buy_spends_each_coin_and_refunds_leftovers() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", uusd(100));
+        sell(&mut deps, "2", uusd(50));
+        sell(&mut deps, "3", Price::Native(coin(30, "uluna")));
+
+        let info = mock_info("buyer", &[coin(40, "uluna"), coin(200, "uusd")]);
+        let msg = batch_buy(&["1", "2", "3"], false);
+        let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        // the payouts of every listing and the refund are merged into one send per recipient
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "buyer".to_string(),
+                    amount: vec![coin(10, "uluna"), coin(50, "uusd")],
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: SELLER.to_string(),
+                    amount: vec![coin(30, "uluna"), coin(150, "uusd")],
+                }),
+                transfer_nft("1", "buyer"),
+                transfer_nft("2", "buyer"),
+                transfer_nft("3", "buyer"),
+            ]
+        );
+        for token_id in &["1", "2", "3"] {
+            assert!(listings()
+                .may_load(
+                    &deps.storage,
+                    (COLLECTION.to_string(), token_id.to_string())
+                )
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn batch_buy_best_effort_skips_failures() {
+        // a failed execution leaves mock storage as is, so every batch starts over
+        let listed = || {
+            let mut deps = setup(0);
+            sell(&mut deps, "1", uusd(100));
+            sell(&mut deps, "2", uusd(100));
+            deps
+        };
+
+        // atomic batches fail as a whole, on a missing listing or running out of funds
+        let info = mock_info("buyer", &[coin(150, "uusd")]);
+        let err = execute(
+            listed().as_mut(),
+            mock_env(),
+            info.clone(),
+            batch_buy(&["1", "3"], false),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::NotListed {});
+        let err = execute(
+            listed().as_mut(),
+            mock_env(),
+            info.clone(),
+            batch_buy(&["1", "2"], false),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidFunds {});
+
+        // best effort batches buy what they can and refund the rest
+        let mut deps = listed();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            batch_buy(&["1", "3", "2"], true),
+        )
+        .unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send("buyer", 50),
+                bank_send(SELLER, 100),
+                transfer_nft("1", "buyer"),
+            ]
+        );
+        let failed: Vec<_> = res
+            .attributes
+            .iter()
+            .filter(|attr| attr.key == "Failed")
+            .map(|attr| attr.value.split(':').nth(1).unwrap())
+            .collect();
+        assert_eq!(failed, vec!["3", "2"]);
+        assert!(listings()
+            .may_load(&deps.storage, (COLLECTION.to_string(), "2".to_string()))
+            .unwrap()
+            .is_some());
+    }
+
+    fn auction_bundle(deps: &mut MarketDeps, token_ids: &[&str]) {
+        for token_id in token_ids {
+            mint(deps, token_id, SELLER);
+        }
+        let msg = ExecuteMsg::AuctionBundle {
+            nfts: token_ids
+                .iter()
+                .map(|token_id| BundleItem {
+                    contract_address: COLLECTION.to_string(),
+                    token_id: token_id.to_string(),
+                })
+                .collect(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::AtHeight(mock_env().block.height + 10),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn bundled_nft_cannot_be_listed_twice() {
+        let mut deps = setup(0);
+        auction_bundle(&mut deps, &["1", "2"]);
+
+        let msg = ExecuteMsg::Sell {
+            token_id: "2".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyListed {});
+        let msg = ExecuteMsg::SellBundle {
+            nfts: vec![BundleItem {
+                contract_address: COLLECTION.to_string(),
+                token_id: "1".to_string(),
+            }],
+            price: uusd(100),
+            expiration: Expiration::Never {},
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::AlreadyListed {});
+
+        // delisting the bundle frees its NFTs
+        let msg = ExecuteMsg::DelistBundle { bundle_id: 0 };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        sell(&mut deps, "2", uusd(100));
+    }
+
+    #[test]
+    fn claim_bundle_refunds_bid_when_nft_moved() {
+        let mut deps = setup(0);
+        auction_bundle(&mut deps, &["1", "2"]);
+        let msg = ExecuteMsg::BidBundle { bundle_id: 0 };
+        let info = mock_info("bidder", &[coin(150, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        mint(&mut deps, "2", "someone");
+
+        let mut env = mock_env();
+        env.block.height += 11;
+        let msg = ExecuteMsg::ClaimBundle { bundle_id: 0 };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 150)]);
+        assert!(BUNDLE_BIDS
+            .may_load(&deps.storage, U64Key::new(0))
+            .unwrap()
+            .is_none());
+        assert!(BUNDLED_NFTS
+            .may_load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn listing_sold_only_by_its_owner() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", uusd(100));
+        mint(&mut deps, "1", "someone");
+        let msg = ExecuteMsg::Buy {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("buyer", &[coin(100, "uusd")]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::NotListed {});
+    }
+
+    #[test]
+    fn accepted_trade_clears_listings() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", uusd(100));
+        mint(&mut deps, "2", "bob");
+        let msg = ExecuteMsg::ProposeTrade {
+            offered: vec![BundleItem {
+                contract_address: COLLECTION.to_string(),
+                token_id: "1".to_string(),
+            }],
+            requested: vec![BundleItem {
+                contract_address: COLLECTION.to_string(),
+                token_id: "2".to_string(),
+            }],
+            extra_funds: None,
+            counterparty: "bob".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        let msg = ExecuteMsg::AcceptTrade { trade_id: 0 };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("bob", &[]), msg).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![transfer_nft("1", "bob"), transfer_nft("2", SELLER)]
+        );
+        assert!(listings()
+            .may_load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn batch_sell_reserves_listings() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let msg = ExecuteMsg::BatchSell {
+            items: vec![SellItem {
+                token_id: "1".to_string(),
+                contract_address: COLLECTION.to_string(),
+                price: uusd(100),
+                expiration: Expiration::Never {},
+                reserved_for: Some("buyer".to_string()),
+            }],
+            best_effort: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+
+        let msg = ExecuteMsg::Buy {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("someone", &[coin(100, "uusd")]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::ReservedForAnotherBuyer {});
+        let info = mock_info("buyer", &[coin(100, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn migrate_rekeys_legacy_listings() {
+        let mut deps = mock_deps();
+        let nft = LegacyNft {
+            token_id: "1".to_string(),
+            owner: SELLER.to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: coin(100, "uusd"),
+            expiration: Expiration::Never {},
+        };
+        LEGACY_LIST
+            .save(&mut deps.storage, "1".to_string(), &nft)
+            .unwrap();
+        let auction_nft = LegacyAuctionNft {
+            token_id: "2".to_string(),
+            owner: SELLER.to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: coin(50, "uusd"),
+            expiration: Expiration::Never {},
+        };
+        LEGACY_AUCTION_LIST
+            .save(&mut deps.storage, "2".to_string(), &auction_nft)
+            .unwrap();
+        let bidder = LegacyBidder {
+            address: "bidder".to_string(),
+            bid: coin(60, "uusd"),
+        };
+        LEGACY_HIGHEST_BIDDER
+            .save(&mut deps.storage, "2".to_string(), &bidder)
+            .unwrap();
+
+        // a contract without a config needs an owner to migrate
+        let msg = MigrateMsg {
+            owner: None,
+            accepted_denoms: None,
+        };
+        let err = migrate(deps.as_mut(), mock_env(), msg).unwrap_err();
+        assert_eq!(err, ContractError::MissingConfig {});
+
+        let msg = MigrateMsg {
+            owner: Some("admin".to_string()),
+            accepted_denoms: None,
+        };
+        migrate(deps.as_mut(), mock_env(), msg).unwrap();
+        let config = query_config(deps.as_ref()).unwrap().config;
+        assert_eq!(config.owner, "admin");
+        assert_eq!(config.accepted_denoms, vec!["uusd".to_string()]);
+        assert_eq!(config.fee_bps, 0);
+
+        let key = (COLLECTION.to_string(), "1".to_string());
+        let listing = listings().load(&deps.storage, key).unwrap();
+        assert_eq!(listing.price, uusd(100));
+        assert!(!listing.escrowed);
+        let key = (COLLECTION.to_string(), "2".to_string());
+        let auction = auction_listings().load(&deps.storage, key.clone()).unwrap();
+        assert_eq!(auction.starting_price, uusd(50));
+        let highest_bid = HIGHEST_BIDDER.load(&deps.storage, key).unwrap();
+        assert_eq!(highest_bid.address, "bidder");
+        assert_eq!(highest_bid.bid, uusd(60));
+
+        // the new indexes cover the migrated listings
+        let nfts = query_listings_by_seller(
+            deps.as_ref(),
+            mock_env(),
+            SELLER.to_string(),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+        .nfts;
+        assert_eq!(nfts.len(), 1);
+        // and nothing is left under the old keys
+        assert!(LEGACY_LIST
+            .may_load(&deps.storage, "1".to_string())
+            .unwrap()
+            .is_none());
+        assert!(LEGACY_AUCTION_LIST
+            .may_load(&deps.storage, "2".to_string())
+            .unwrap()
+            .is_none());
+        assert!(LEGACY_HIGHEST_BIDDER
+            .may_load(&deps.storage, "2".to_string())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn outbid_bidder_is_refunded() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", Price::Native(coin(100, "uluna")), None);
+        let res = bid(&mut deps, "alice", coin(150, "uluna")).unwrap();
+        assert!(res.messages.is_empty());
+
+        // the refund is the whole escrowed bid, in the asset it was made in
+        let res = bid(&mut deps, "bob", coin(200, "uluna")).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "alice".to_string(),
+                amount: vec![coin(150, "uluna")],
+            })]
+        );
+        let highest_bid =
+            query_highest_bidder(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+                .unwrap()
+                .bidder;
+        assert_eq!(highest_bid.address, "bob");
+        assert_eq!(highest_bid.bid, Price::Native(coin(200, "uluna")));
+
+        // bids in another asset are refused rather than mixed in
+        let err = bid(&mut deps, "carol", coin(300, "uusd")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidDenomination {});
+    }
+
+    #[test]
+    fn bids_meet_starting_price_and_increment() {
+        let mut deps = setup(0);
+        auction(
+            &mut deps,
+            "1",
+            uusd(100),
+            Some(BidIncrement::Absolute(Uint128::new(10))),
+        );
+        // the first bid has to meet the starting price, and may equal it
+        let err = bid(&mut deps, "alice", coin(99, "uusd")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BidBelowMinimum {
+                min: Uint128::new(100)
+            }
+        );
+        bid(&mut deps, "alice", coin(100, "uusd")).unwrap();
+
+        // later bids have to beat the highest bid by the increment
+        let err = bid(&mut deps, "bob", coin(100, "uusd")).unwrap_err();
+        assert_eq!(err, ContractError::UnsurpassedHighestBid {});
+        let err = bid(&mut deps, "bob", coin(109, "uusd")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BidBelowMinimum {
+                min: Uint128::new(110)
+            }
+        );
+        bid(&mut deps, "bob", coin(110, "uusd")).unwrap();
+    }
+
+    #[test]
+    fn bid_increment_in_basis_points() {
+        let mut deps = setup(0);
+        auction(
+            &mut deps,
+            "1",
+            uusd(100),
+            Some(BidIncrement::BasisPoints(1_000)),
+        );
+        bid(&mut deps, "alice", coin(200, "uusd")).unwrap();
+        // 10% of the highest bid
+        let err = bid(&mut deps, "bob", coin(219, "uusd")).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::BidBelowMinimum {
+                min: Uint128::new(220)
+            }
+        );
+        bid(&mut deps, "bob", coin(220, "uusd")).unwrap();
+    }
+
+    // Tokens of two collections whose names and ids share prefixes
+    const PAGED_KEYS: [(&str, &str); 6] = [
+        ("coll", "1"),
+        ("coll", "10"),
+        ("coll", "2"),
+        (COLLECTION, "1"),
+        (COLLECTION, "10"),
+        (COLLECTION, "2"),
+    ];
+
+    // Pages through query one entry at a time, resuming after the last key returned
+    fn page_by_one<F>(query: F) -> Vec<(String, String)>
+    where
+        F: Fn(Option<(String, String)>) -> Vec<(String, String)>,
+    {
+        let mut keys: Vec<(String, String)> = vec![];
+        loop {
+            let page = query(keys.last().cloned());
+            assert!(page.len() <= 1);
+            if page.is_empty() {
+                return keys;
+            }
+            keys.extend(page);
+        }
+    }
+
+    #[test]
+    fn listings_page_without_skipping_or_repeating() {
+        let mut deps = setup(0);
+        for (collection, token_id) in PAGED_KEYS.iter() {
+            mint_in(&mut deps, collection, token_id, SELLER);
+            let msg = ExecuteMsg::Sell {
+                token_id: token_id.to_string(),
+                contract_address: collection.to_string(),
+                price: uusd(100),
+                expiration: Expiration::Never {},
+                reserved_for: None,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        }
+
+        let all: Vec<_> = query_listings(deps.as_ref(), mock_env(), None, None, None)
+            .unwrap()
+            .nfts
+            .into_iter()
+            .map(|nft| (nft.contract_address, nft.token_id))
+            .collect();
+        assert_eq!(all.len(), PAGED_KEYS.len());
+        let paged = page_by_one(|start_after| {
+            query_listings(deps.as_ref(), mock_env(), start_after, None, Some(1))
+                .unwrap()
+                .nfts
+                .into_iter()
+                .map(|nft| (nft.contract_address, nft.token_id))
+                .collect()
+        });
+        assert_eq!(paged, all);
+    }
+
+    #[test]
+    fn auction_listings_page_without_skipping_or_repeating() {
+        let mut deps = setup(0);
+        for (collection, token_id) in PAGED_KEYS.iter() {
+            mint_in(&mut deps, collection, token_id, SELLER);
+            let msg = ExecuteMsg::Auction {
+                token_id: token_id.to_string(),
+                contract_address: collection.to_string(),
+                starting_price: uusd(100),
+                min_bid_increment: None,
+                expiration: Expiration::AtHeight(mock_env().block.height + 10),
+                reserve_price: None,
+                buy_now_price: None,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        }
+
+        let all: Vec<_> = query_auction_listings(deps.as_ref(), mock_env(), None, None, None)
+            .unwrap()
+            .auction_nfts
+            .into_iter()
+            .map(|nft| (nft.contract_address, nft.token_id))
+            .collect();
+        assert_eq!(all.len(), PAGED_KEYS.len());
+        let paged = page_by_one(|start_after| {
+            query_auction_listings(deps.as_ref(), mock_env(), start_after, None, Some(1))
+                .unwrap()
+                .auction_nfts
+                .into_iter()
+                .map(|nft| (nft.contract_address, nft.token_id))
+                .collect()
+        });
+        assert_eq!(paged, all);
+    }
+
+    #[test]
+    fn listings_by_price_range_page_through_equal_prices() {
+        let mut deps = setup(0);
+        for (token_id, amount) in &[
+            ("1", 100),
+            ("2", 200),
+            ("3", 200),
+            ("4", 200),
+            ("5", 300),
+            ("6", 99),
+            ("7", 301),
+        ] {
+            sell(&mut deps, token_id, uusd(*amount));
+        }
+        sell(&mut deps, "8", Price::Native(coin(200, "uluna")));
+        mint(&mut deps, "9", SELLER);
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::DutchAuction {
+            token_id: "9".to_string(),
+            contract_address: COLLECTION.to_string(),
+            start_price: uusd(200),
+            end_price: Uint128::new(150),
+            start_time: now,
+            end_time: now.plus_seconds(100),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+
+        let mut start_after = None;
+        let mut seen = vec![];
+        loop {
+            let nfts = query_listings_by_price_range(
+                deps.as_ref(),
+                mock_env(),
+                "uusd".to_string(),
+                Some(Uint128::new(100)),
+                Some(Uint128::new(300)),
+                start_after,
+                None,
+                Some(1),
+            )
+            .unwrap()
+            .nfts;
+            assert!(nfts.len() <= 1);
+            let nft = match nfts.into_iter().next() {
+                Some(nft) => nft,
+                None => break,
+            };
+            start_after = Some((
+                nft.price.amount(),
+                (nft.contract_address, nft.token_id.clone()),
+            ));
+            seen.push(nft.token_id);
+        }
+        assert_eq!(seen, vec!["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn prune_expired_removes_every_listing_nobody_bid_on() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        auction_bundle(&mut deps, &["2", "3"]);
+        auction(&mut deps, "4", uusd(100), None);
+        auction(&mut deps, "5", uusd(100), None);
+        let msg = ExecuteMsg::Bid {
+            token_id: "5".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("bidder", &[coin(100, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        mint(&mut deps, "6", SELLER);
+        let msg = ExecuteMsg::Sell {
+            token_id: "6".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration: Expiration::AtHeight(mock_env().block.height + 10),
+            reserved_for: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        sell(&mut deps, "7", uusd(100));
+
+        let mut env = mock_env();
+        env.block.height += 10;
+        env.block.time = env.block.time.plus_seconds(200);
+        let token_ids =
+            |nfts: Vec<NFT>| -> Vec<String> { nfts.into_iter().map(|nft| nft.token_id).collect() };
+        // expired entries are only returned on request
+        let live = query_listings(deps.as_ref(), env.clone(), None, None, None).unwrap();
+        assert_eq!(token_ids(live.nfts), vec!["7"]);
+        let all = query_listings(deps.as_ref(), env.clone(), None, Some(true), None).unwrap();
+        assert_eq!(token_ids(all.nfts), vec!["6", "7"]);
+        let live = query_auction_listings(deps.as_ref(), env.clone(), None, None, None).unwrap();
+        assert!(live.auction_nfts.is_empty());
+        let all =
+            query_auction_listings(deps.as_ref(), env.clone(), None, Some(true), None).unwrap();
+        assert_eq!(all.auction_nfts.len(), 2);
+
+        let msg = ExecuteMsg::PruneExpired { limit: None };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+        assert!(res.messages.is_empty());
+        let pruned: Vec<_> = res
+            .attributes
+            .iter()
+            .map(|attr| (attr.key.as_str(), attr.value.as_str()))
+            .collect();
+        assert_eq!(
+            pruned,
+            vec![
+                ("action", "prune_expired"),
+                ("listings", "1"),
+                ("auction_listings", "1"),
+                ("sealed_auctions", "1"),
+                ("bundles", "1"),
+            ]
+        );
+        let all = query_listings(deps.as_ref(), env.clone(), None, Some(true), None).unwrap();
+        assert_eq!(token_ids(all.nfts), vec!["7"]);
+        // the auction with a bid is left to be claimed
+        let all =
+            query_auction_listings(deps.as_ref(), env.clone(), None, Some(true), None).unwrap();
+        let auctioned: Vec<_> = all
+            .auction_nfts
+            .into_iter()
+            .map(|auction_nft| auction_nft.token_id)
+            .collect();
+        assert_eq!(auctioned, vec!["5"]);
+        assert!(!SEALED_AUCTIONS.has(&deps.storage, (COLLECTION.to_string(), "1".to_string())));
+        assert!(!BUNDLES.has(&deps.storage, U64Key::new(0)));
+        // the pruned NFTs can be listed again
+        for token_id in &["1", "2", "3", "4", "6"] {
+            assert!(listed_by(&deps.storage, COLLECTION, token_id)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn prune_expired_keeps_sealed_auctions_holding_deposits() {
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        commit_bid(&mut deps, "bidder", 150, 120);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(200);
+        let msg = ExecuteMsg::PruneExpired { limit: None };
+        execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert!(SEALED_AUCTIONS.has(&deps.storage, (COLLECTION.to_string(), "1".to_string())));
+    }
+
+    fn cw20(amount: u128) -> Price {
+        Price::Cw20(Cw20Coin {
+            address: "token".to_string(),
+            amount: Uint128::new(amount),
+        })
+    }
+
+    // Sends amount of the cw20 token contract to the marketplace on behalf of sender
+    fn receive_cw20(
+        deps: &mut MarketDeps,
+        token: &str,
+        sender: &str,
+        amount: u128,
+        msg: ReceiveMsg,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: sender.to_string(),
+            amount: Uint128::new(amount),
+            msg: to_binary(&msg).unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(token, &[]), msg)
+    }
+
+    fn buy_msg(token_id: &str) -> ReceiveMsg {
+        ReceiveMsg::Buy {
+            token_id: token_id.to_string(),
+            contract_address: COLLECTION.to_string(),
+        }
+    }
+
+    #[test]
+    fn cw20_buy_pays_seller_in_the_token() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", cw20(100));
+
+        let res = receive_cw20(&mut deps, "token", "buyer", 100, buy_msg("1")).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                cw20(100).into_msg(SELLER.to_string()).unwrap(),
+                transfer_nft("1", "buyer"),
+            ]
+        );
+        let listing = listings().may_load(&deps.storage, (COLLECTION.to_string(), "1".to_string()));
+        assert_eq!(listing.unwrap(), None);
+    }
+
+    #[test]
+    fn cw20_bid_refunds_outbid_bidder_in_the_token() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", cw20(100), None);
+        let bid_msg = || ReceiveMsg::Bid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+
+        let res = receive_cw20(&mut deps, "token", "alice", 100, bid_msg()).unwrap();
+        assert!(res.messages.is_empty());
+        let res = receive_cw20(&mut deps, "token", "bob", 110, bid_msg()).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![cw20(100).into_msg("alice".to_string()).unwrap()]
+        );
+        let highest_bid = HIGHEST_BIDDER
+            .load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap();
+        assert_eq!(highest_bid.address, "bob");
+        assert_eq!(highest_bid.bid, cw20(110));
+    }
+
+    #[test]
+    fn cw20_not_accepted_is_rejected() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let msg = ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: Price::Cw20(Cw20Coin {
+                address: "other".to_string(),
+                amount: Uint128::new(100),
+            }),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidDenomination {});
+    }
+
+    #[test]
+    fn cw20_payment_asset_is_the_sending_contract() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", cw20(100));
+
+        // another cw20 contract can't pay for a listing priced in token, whatever it claims
+        let err = receive_cw20(&mut deps, "other", "buyer", 100, buy_msg("1")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidDenomination {});
+        // and the buyer named in the hook can't pay with tokens it did not send
+        let err = receive_cw20(&mut deps, "buyer", "buyer", 100, buy_msg("1")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidDenomination {});
+        assert!(listings()
+            .may_load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap()
+            .is_some());
+        receive_cw20(&mut deps, "token", "buyer", 100, buy_msg("1")).unwrap();
+    }
+
+    fn make_offer(
+        deps: &mut MarketDeps,
+        bidder: &str,
+        amount: u128,
+        expiration: Expiration,
+    ) -> Response {
+        let msg = ExecuteMsg::MakeOffer {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            expiration,
+        };
+        let info = mock_info(bidder, &[coin(amount, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap()
+    }
+
+    fn accept_offer(
+        deps: &mut MarketDeps,
+        env: Env,
+        bidder: &str,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::AcceptOffer {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            bidder: bidder.to_string(),
+        };
+        execute(deps.as_mut(), env, mock_info(SELLER, &[]), msg)
+    }
+
+    #[test]
+    fn offers_are_replaced_and_cancelled_with_refunds() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+
+        let res = make_offer(&mut deps, "bidder", 100, Expiration::Never {});
+        assert!(res.messages.is_empty());
+        // a new offer refunds the one it replaces
+        let res = make_offer(&mut deps, "bidder", 150, Expiration::Never {});
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 100)]);
+        let offers = query_offers_by_token(
+            deps.as_ref(),
+            "1".to_string(),
+            COLLECTION.to_string(),
+            None,
+            None,
+        )
+        .unwrap()
+        .offers;
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].price, uusd(150));
+
+        let msg = ExecuteMsg::CancelOffer {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bidder", &[]),
+            msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 150)]);
+        let err = execute(deps.as_mut(), mock_env(), mock_info("bidder", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NoOffer {});
+    }
+
+    #[test]
+    fn accepted_offer_clears_listings_of_the_nft() {
+        let mut deps = setup(0);
+        auction_bundle(&mut deps, &["1", "2"]);
+        let msg = ExecuteMsg::BidBundle { bundle_id: 0 };
+        let info = mock_info("bidder", &[coin(100, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        make_offer(&mut deps, "buyer", 200, Expiration::Never {});
+
+        let res = accept_offer(&mut deps, mock_env(), "buyer").unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send(SELLER, 200),
+                bank_send("bidder", 100),
+                transfer_nft("1", "buyer"),
+            ]
+        );
+        assert!(!BUNDLES.has(&deps.storage, U64Key::new(0)));
+        let offer = offers().may_load(
+            &deps.storage,
+            (COLLECTION.to_string(), "1".to_string(), "buyer".to_string()),
+        );
+        assert_eq!(offer.unwrap(), None);
+        for token_id in &["1", "2"] {
+            assert!(listed_by(&deps.storage, COLLECTION, token_id)
+                .unwrap()
+                .is_empty());
+        }
+    }
+
+    #[test]
+    fn expired_offer_cannot_be_accepted() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let expiration = Expiration::AtHeight(mock_env().block.height + 1);
+        make_offer(&mut deps, "buyer", 200, expiration);
+
+        let mut env = mock_env();
+        env.block.height += 1;
+        let err = accept_offer(&mut deps, env, "buyer").unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+    }
+
+    fn make_collection_offer(deps: &mut MarketDeps, bidder: &str, amount: u128, quantity: u64) {
+        let msg = ExecuteMsg::MakeCollectionOffer {
+            collection: COLLECTION.to_string(),
+            price: uusd(amount),
+            quantity,
+            expiration: Expiration::Never {},
+        };
+        let info = mock_info(bidder, &[coin(amount * quantity as u128, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+
+    #[test]
+    fn accepted_collection_offer_clears_listings_of_the_nft() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", uusd(100), None);
+        make_collection_offer(&mut deps, "buyer", 200, 2);
+        // bids are settled through Claim, so an auctioned NFT can't be sold to an offer
+        let msg = ExecuteMsg::AcceptCollectionOffer {
+            collection: COLLECTION.to_string(),
+            token_id: "1".to_string(),
+            bidder: "buyer".to_string(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(SELLER, &[]),
+            msg.clone(),
+        );
+        assert_eq!(err.unwrap_err(), ContractError::OngoingAuction {});
+
+        let mut deps = setup(0);
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        commit_bid(&mut deps, "bidder", 150, 120);
+        make_collection_offer(&mut deps, "buyer", 200, 2);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send(SELLER, 200),
+                bank_send("bidder", 150),
+                transfer_nft("1", "buyer"),
+            ]
+        );
+        assert!(!SEALED_AUCTIONS.has(&deps.storage, (COLLECTION.to_string(), "1".to_string())));
+        assert!(listed_by(&deps.storage, COLLECTION, "1")
+            .unwrap()
+            .is_empty());
+        let offer = collection_offers()
+            .load(&deps.storage, (COLLECTION.to_string(), "buyer".to_string()))
+            .unwrap();
+        assert_eq!(offer.quantity, 1);
+    }
+
+    #[test]
+    fn collection_offers_page_through_equal_prices() {
+        let mut deps = setup(0);
+        for (bidder, amount) in &[
+            ("alice", 100),
+            ("bob", 200),
+            ("carol", 200),
+            ("dave", 200),
+            ("erin", 150),
+        ] {
+            make_collection_offer(&mut deps, bidder, *amount, 1);
+        }
+
+        let mut start_after = None;
+        let mut seen = vec![];
+        loop {
+            let offers = query_collection_offers(
+                deps.as_ref(),
+                mock_env(),
+                COLLECTION.to_string(),
+                "uusd".to_string(),
+                start_after,
+                None,
+                Some(1),
+            )
+            .unwrap()
+            .offers;
+            assert!(offers.len() <= 1);
+            let offer = match offers.into_iter().next() {
+                Some(offer) => offer,
+                None => break,
+            };
+            start_after = Some((offer.price.amount(), offer.bidder.clone()));
+            seen.push(offer.bidder);
+        }
+        // best offers first, equal prices in reverse bidder order
+        assert_eq!(seen, vec!["dave", "carol", "bob", "erin", "alice"]);
+    }
+
+    // Auctions token "1" from 100 for 10 blocks
+    fn auction_with(
+        deps: &mut MarketDeps,
+        reserve_price: Option<ReservePrice>,
+        buy_now_price: Option<u128>,
+    ) -> Result<Response, ContractError> {
+        mint(deps, "1", SELLER);
+        let msg = ExecuteMsg::Auction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::AtHeight(mock_env().block.height + 10),
+            reserve_price,
+            buy_now_price: buy_now_price.map(Uint128::new),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg)
+    }
+
+    fn reserve(amount: u128, hidden: bool) -> Option<ReservePrice> {
+        Some(ReservePrice {
+            amount: Uint128::new(amount),
+            hidden,
+        })
+    }
+
+    #[test]
+    fn buy_now_settles_immediately() {
+        let mut deps = setup(0);
+        auction_with(&mut deps, None, Some(500)).unwrap();
+        bid(&mut deps, "alice", coin(100, "uusd")).unwrap();
+
+        let res = bid(&mut deps, "bob", coin(600, "uusd")).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send(SELLER, 500),
+                transfer_nft("1", "bob"),
+                bank_send("bob", 100),
+                bank_send("alice", 100),
+            ]
+        );
+        let key = (COLLECTION.to_string(), "1".to_string());
+        assert_eq!(
+            auction_listings()
+                .may_load(&deps.storage, key.clone())
+                .unwrap(),
+            None
+        );
+        assert_eq!(HIGHEST_BIDDER.may_load(&deps.storage, key).unwrap(), None);
+    }
+
+    #[test]
+    fn unmet_reserve_refunds_bid_and_returns_escrowed_nft() {
+        let mut deps = setup(0);
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: SELLER.to_string(),
+            token_id: "1".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                starting_price: uusd(100),
+                min_bid_increment: None,
+                expiration: Expiration::AtHeight(mock_env().block.height + 10),
+                reserve_price: reserve(300, true),
+                buy_now_price: None,
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), mock_env(), mock_info(COLLECTION, &[]), msg).unwrap();
+        mint(&mut deps, "1", MOCK_CONTRACT_ADDR);
+        bid(&mut deps, "alice", coin(200, "uusd")).unwrap();
+
+        let mut env = mock_env();
+        env.block.height += 10;
+        let msg = ExecuteMsg::Claim {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let res = execute(deps.as_mut(), env, mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![bank_send("alice", 200), transfer_nft("1", SELLER)]
+        );
+        assert!(res
+            .attributes
+            .iter()
+            .any(|attr| attr.key == "Action" && attr.value == "ReserveNotMet"));
+    }
+
+    #[test]
+    fn hidden_reserve_is_redacted_in_queries() {
+        let mut deps = setup(0);
+        // the buy now price may be below a hidden reserve, but not below a visible one
+        let err = auction_with(&mut deps, reserve(1_000, false), Some(500)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidAmount {});
+        auction_with(&mut deps, reserve(1_000, true), Some(500)).unwrap();
+
+        let auction_nft =
+            query_auction_listing(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+                .unwrap()
+                .auction_nft;
+        assert_eq!(auction_nft.reserve_price, reserve(0, true));
+        let auction_nfts = query_auction_listings(deps.as_ref(), mock_env(), None, None, None)
+            .unwrap()
+            .auction_nfts;
+        assert_eq!(auction_nfts[0].reserve_price, reserve(0, true));
+        // the reserve is still enforced
+        let stored = auction_listings()
+            .load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap();
+        assert_eq!(stored.reserve_price, reserve(1_000, true));
+
+        let mut deps = setup(0);
+        auction_with(&mut deps, reserve(1_000, false), None).unwrap();
+        let auction_nft =
+            query_auction_listing(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+                .unwrap()
+                .auction_nft;
+        assert_eq!(auction_nft.reserve_price, reserve(1_000, false));
+    }
+
+    // A dutch auction of token "1" from 1_000 down to 100 over the next 100 seconds
+    fn dutch_auction_listing(deps: &mut MarketDeps) {
+        mint(deps, "1", SELLER);
+        let now = mock_env().block.time;
+        let msg = ExecuteMsg::DutchAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            start_price: uusd(1_000),
+            end_price: Uint128::new(100),
+            start_time: now,
+            end_time: now.plus_seconds(100),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn dutch_auction_price_follows_schedule() {
+        let now = mock_env().block.time;
+        let dutch_auction = DutchAuction {
+            end_price: Uint128::new(100),
+            start_time: now.plus_seconds(10),
+            end_time: now.plus_seconds(110),
+        };
+        let price_after = |seconds: u64| {
+            let mut block = mock_env().block;
+            block.time = now.plus_seconds(seconds);
+            dutch_auction.price_at(Uint128::new(1_000), &block).u128()
+        };
+        assert_eq!(price_after(0), 1_000);
+        assert_eq!(price_after(10), 1_000);
+        assert_eq!(price_after(35), 775);
+        assert_eq!(price_after(60), 550);
+        assert_eq!(price_after(110), 100);
+        assert_eq!(price_after(1_000), 100);
+    }
+
+    #[test]
+    fn dutch_auction_buy_refunds_overpayment() {
+        let mut deps = setup(0);
+        dutch_auction_listing(&mut deps);
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(50);
+        let msg = ExecuteMsg::Buy {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("buyer", &[coin(500, "uusd")]);
+        let err = execute(deps.as_mut(), env.clone(), info, msg.clone()).unwrap_err();
+        assert_eq!(err, ContractError::InvalidAmount {});
+        let info = mock_info("buyer", &[coin(600, "uusd")]);
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![
+                bank_send("buyer", 50),
+                bank_send(SELLER, 550),
+                transfer_nft("1", "buyer"),
+            ]
+        );
+    }
+
+    #[test]
+    fn approval_has_to_outlast_listing() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let height = mock_env().block.height;
+        deps.querier
+            .approval_expiries
+            .insert(SELLER.to_string(), Expiration::AtHeight(height + 5));
+        let sell = |expiration| ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(100),
+            expiration,
+            reserved_for: None,
+        };
+
+        for expiration in vec![
+            Expiration::AtHeight(height + 10),
+            Expiration::Never {},
+            Expiration::AtTime(mock_env().block.time.plus_seconds(1)),
+        ] {
+            let msg = sell(expiration);
+            let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg);
+            assert_eq!(err.unwrap_err(), ContractError::Unapproved {});
+        }
+        let msg = sell(Expiration::AtHeight(height + 5));
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn bid_history_is_kept_per_auction() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", uusd(100), None);
+        bid(&mut deps, "alice", coin(100, "uusd")).unwrap();
+        bid(&mut deps, "bob", coin(150, "uusd")).unwrap();
+        let mut env = mock_env();
+        env.block.height += 10;
+        let msg = ExecuteMsg::Claim {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("anyone", &[]), msg).unwrap();
+
+        // the winner auctions the token again right away
+        mint(&mut deps, "1", "bob");
+        let msg = ExecuteMsg::Auction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::AtHeight(env.block.height + 10),
+            reserve_price: None,
+            buy_now_price: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("bob", &[]), msg).unwrap();
+        let auction_id = auction_listings()
+            .load(&deps.storage, (COLLECTION.to_string(), "1".to_string()))
+            .unwrap()
+            .auction_id;
+        assert_eq!(auction_id, 1);
+        let msg = ExecuteMsg::Bid {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info("carol", &[coin(100, "uusd")]);
+        execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let bids = |auction_id| -> Vec<(u64, String, Price)> {
+            query_bids(deps.as_ref(), auction_id, None, None)
+                .unwrap()
+                .bids
+                .into_iter()
+                .map(|record| (record.id, record.bidder, record.bid))
+                .collect()
+        };
+        assert_eq!(
+            bids(0),
+            vec![
+                (0, "alice".to_string(), uusd(100)),
+                (1, "bob".to_string(), uusd(150)),
+            ]
+        );
+        assert_eq!(bids(1), vec![(0, "carol".to_string(), uusd(100))]);
+    }
+
+    fn cancel_auction(deps: &mut MarketDeps, funds: &[Coin]) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::CancelAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(SELLER, funds), msg)
+    }
+
+    fn set_cancel_penalty(deps: &mut MarketDeps, cancel_penalty: CancelPenalty) {
+        let msg = ExecuteMsg::UpdateConfig {
+            owner: None,
+            accepted_denoms: None,
+            accepted_cw20s: None,
+            fee_bps: None,
+            fee_collector: None,
+            auction_extension: None,
+            cancel_penalty: Some(cancel_penalty),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("admin", &[]), msg).unwrap();
+    }
+
+    #[test]
+    fn auction_without_bids_can_be_cancelled() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", uusd(100), None);
+
+        let err = cancel_auction(&mut deps, &[coin(10, "uusd")]).unwrap_err();
+        assert_eq!(err, ContractError::InvalidFunds {});
+        let res = cancel_auction(&mut deps, &[]).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(listed_by(&deps.storage, COLLECTION, "1")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn auction_with_bids_cannot_be_cancelled_when_disallowed() {
+        let mut deps = setup(0);
+        auction(&mut deps, "1", uusd(100), None);
+        bid(&mut deps, "alice", coin(200, "uusd")).unwrap();
+
+        let err = cancel_auction(&mut deps, &[]).unwrap_err();
+        assert_eq!(err, ContractError::AuctionHasBids {});
+        let err = cancel_auction(&mut deps, &[coin(20, "uusd")]).unwrap_err();
+        assert_eq!(err, ContractError::AuctionHasBids {});
+        let key = (COLLECTION.to_string(), "1".to_string());
+        assert!(HIGHEST_BIDDER.has(&deps.storage, key));
+    }
+
+    #[test]
+    fn cancel_penalty_goes_to_the_outbid_bidder() {
+        let mut deps = setup(0);
+        set_cancel_penalty(&mut deps, CancelPenalty::BasisPoints(1_000));
+        auction(&mut deps, "1", uusd(100), None);
+        bid(&mut deps, "alice", coin(200, "uusd")).unwrap();
+
+        // the penalty is 10% of the highest bid, in its asset
+        for funds in &[vec![], vec![coin(10, "uusd")], vec![coin(20, "uluna")]] {
+            let err = cancel_auction(&mut deps, funds).unwrap_err();
+            assert!(matches!(
+                err,
+                ContractError::InvalidFunds {} | ContractError::InvalidDenomination {}
+            ));
+        }
+        let res = cancel_auction(&mut deps, &[coin(20, "uusd")]).unwrap();
+        assert_eq!(
+            sent_messages(&res),
+            vec![bank_send("alice", 200), bank_send("alice", 20)]
+        );
+        let key = (COLLECTION.to_string(), "1".to_string());
+        assert_eq!(HIGHEST_BIDDER.may_load(&deps.storage, key).unwrap(), None);
+        assert!(listed_by(&deps.storage, COLLECTION, "1")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn finished_auction_cannot_be_cancelled() {
+        let mut deps = setup(0);
+        set_cancel_penalty(&mut deps, CancelPenalty::BasisPoints(1_000));
+        auction(&mut deps, "1", uusd(100), None);
+        bid(&mut deps, "alice", coin(200, "uusd")).unwrap();
+
+        let mut env = mock_env();
+        env.block.height += 10;
+        let msg = ExecuteMsg::CancelAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+        };
+        let info = mock_info(SELLER, &[coin(20, "uusd")]);
+        let err = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Expired {});
+    }
+
+    #[test]
+    fn auction_has_to_expire() {
+        let mut deps = setup(0);
+        mint(&mut deps, "1", SELLER);
+        let never_expiring = ContractError::CustomError {
+            val: "an auction has to expire".to_string(),
+        };
+        let msg = ExecuteMsg::Auction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: uusd(100),
+            min_bid_increment: None,
+            expiration: Expiration::Never {},
+            reserve_price: None,
+            buy_now_price: None,
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, never_expiring);
+
+        let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+            sender: SELLER.to_string(),
+            token_id: "2".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                starting_price: uusd(100),
+                min_bid_increment: None,
+                expiration: Expiration::Never {},
+                reserve_price: None,
+                buy_now_price: None,
+            })
+            .unwrap(),
+        });
+        let err = execute(deps.as_mut(), mock_env(), mock_info(COLLECTION, &[]), msg).unwrap_err();
+        assert_eq!(err, never_expiring);
+
+        auction(&mut deps, "1", uusd(100), None);
+        let msg = ExecuteMsg::UpdateAuction {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            starting_price: None,
+            expiration: Some(Expiration::Never {}),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info(SELLER, &[]), msg).unwrap_err();
+        assert_eq!(err, never_expiring);
+    }
+
+    fn update_price(
+        deps: &mut MarketDeps,
+        sender: &str,
+        price: Price,
+    ) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::UpdatePrice {
+            collection: COLLECTION.to_string(),
+            token_id: "1".to_string(),
+            price,
+            expiration: Expiration::Never {},
+        };
+        execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg)
+    }
+
+    fn uusd_listings_priced(deps: &MarketDeps, amount: u128) -> Vec<String> {
+        query_listings_by_price_range(
+            deps.as_ref(),
+            mock_env(),
+            "uusd".to_string(),
+            Some(Uint128::new(amount)),
+            Some(Uint128::new(amount)),
+            None,
+            None,
+            None,
+        )
+        .unwrap()
+        .nfts
+        .into_iter()
+        .map(|nft| nft.token_id)
+        .collect()
+    }
+
+    #[test]
+    fn only_seller_can_update_price() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", uusd(100));
+
+        let err = update_price(&mut deps, "anyone", uusd(200)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        // nor can the seller once the NFT changed hands
+        mint(&mut deps, "1", "buyer");
+        let err = update_price(&mut deps, SELLER, uusd(200)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+        let err = update_price(&mut deps, "buyer", uusd(200)).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+
+    #[test]
+    fn dutch_auction_price_cannot_be_updated() {
+        let mut deps = setup(0);
+        dutch_auction_listing(&mut deps);
+
+        let err = update_price(&mut deps, SELLER, uusd(200)).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CustomError {
+                val: "dutch auction prices can't be updated".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn updated_price_replaces_price_index_entry() {
+        let mut deps = setup(0);
+        sell(&mut deps, "1", uusd(100));
+        assert_eq!(uusd_listings_priced(&deps, 100), vec!["1"]);
+
+        update_price(&mut deps, SELLER, uusd(200)).unwrap();
+        assert!(uusd_listings_priced(&deps, 100).is_empty());
+        assert_eq!(uusd_listings_priced(&deps, 200), vec!["1"]);
+        let nft = query_listing(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+            .unwrap()
+            .nft;
+        assert_eq!(nft.price, uusd(200));
+    }
+
+    fn clear_stale_listings(deps: &mut MarketDeps) -> Result<Response, ContractError> {
+        let msg = ExecuteMsg::ClearStaleListings {
+            contract_address: COLLECTION.to_string(),
+            token_id: "1".to_string(),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg)
+    }
+
+    #[test]
+    fn stale_bundle_does_not_block_new_owner() {
+        let mut deps = setup(0);
+        auction_bundle(&mut deps, &["1", "2"]);
+        let msg = ExecuteMsg::BidBundle { bundle_id: 0 };
+        let info = mock_info("bidder", &[coin(100, "uusd")]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+        // the seller transfers the NFT away outside the marketplace
+        mint(&mut deps, "1", "buyer");
+        let msg = ExecuteMsg::Sell {
+            token_id: "1".to_string(),
+            contract_address: COLLECTION.to_string(),
+            price: uusd(300),
+            expiration: Expiration::Never {},
+            reserved_for: None,
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("buyer", &[]), msg).unwrap();
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 100)]);
+        assert!(!BUNDLES.has(&deps.storage, U64Key::new(0)));
+        assert!(listed_by(&deps.storage, COLLECTION, "2")
+            .unwrap()
+            .is_empty());
+        let nft = query_listing(deps.as_ref(), "1".to_string(), COLLECTION.to_string())
+            .unwrap()
+            .nft;
+        assert_eq!(nft.owner, "buyer");
+        // the new listing is live, so it is not anyone's to clear
+        let err = clear_stale_listings(&mut deps).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+    }
+
+    #[test]
+    fn anyone_can_clear_stale_sealed_auction() {
+        let mut deps = setup(0);
+        let err = clear_stale_listings(&mut deps).unwrap_err();
+        assert_eq!(err, ContractError::NotListed {});
+        start_sealed_auction(&mut deps, SealedBidPricing::FirstPrice);
+        commit_bid(&mut deps, "bidder", 150, 120);
+        let err = clear_stale_listings(&mut deps).unwrap_err();
+        assert_eq!(err, ContractError::Unauthorized {});
+
+        mint(&mut deps, "1", "buyer");
+        let res = clear_stale_listings(&mut deps).unwrap();
+        assert_eq!(sent_messages(&res), vec![bank_send("bidder", 150)]);
+        assert!(listed_by(&deps.storage, COLLECTION, "1")
+            .unwrap()
+            .is_empty());
+        let bids = query_sealed_bids(
+            deps.as_ref(),
+            "1".to_string(),
+            COLLECTION.to_string(),
+            None,
+            None,
+        )
+        .unwrap()
+        .bids;
+        assert!(bids.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -35,6 +35,39 @@ pub enum ContractError {
 
     #[error("Unapproved")]
     Unapproved {},
+
+    #[error("Invalid Funds")]
+    InvalidFunds {},
+
+    #[error("Bid Below Minimum: {min}")]
+    BidBelowMinimum { min: Uint128 },
+
+    #[error("Missing Config")]
+    MissingConfig {},
+
+    #[error("Invalid Fee")]
+    InvalidFee {},
+
+    #[error("Invalid Royalty")]
+    InvalidRoyalty {},
+
+    #[error("No Offer")]
+    NoOffer {},
+
+    #[error("Auction Not Started")]
+    AuctionNotStarted {},
+
+    #[error("Already Committed")]
+    AlreadyCommitted {},
+
+    #[error("Invalid Reveal")]
+    InvalidReveal {},
+
+    #[error("Auction Has Bids")]
+    AuctionHasBids {},
+
+    #[error("Reserved For Another Buyer")]
+    ReservedForAnotherBuyer {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -1,24 +1,132 @@
-use cosmwasm_std::Coin;
-use cw721::Expiration;
+use cosmwasm_std::{Timestamp, Uint128};
+use cw20::Cw20ReceiveMsg;
+use cw721::{Cw721ReceiveMsg, Expiration};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::state::{AuctionNft, Bidder, NFT};
+use crate::state::{
+    AuctionExtension, AuctionNft, BidIncrement, BidRecord, Bidder, Bundle, BundleItem,
+    CancelPenalty, CollectionOffer, Config, Offer, Price, ReservePrice, SealedAuction, SealedBid,
+    SealedBidPricing, Trade, NFT,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct InstantiateMsg {}
+pub struct InstantiateMsg {
+    /// Defaults to the instantiating address
+    pub owner: Option<String>,
+    pub accepted_denoms: Vec<String>,
+    #[serde(default)]
+    pub accepted_cw20s: Vec<String>,
+    /// Marketplace fee in basis points, at most 10_000
+    pub fee_bps: u64,
+    /// Defaults to the owner
+    pub fee_collector: Option<String>,
+    /// Disabled unless given
+    #[serde(default)]
+    pub auction_extension: Option<AuctionExtension>,
+    /// Defaults to disallowing cancellation after the first bid
+    #[serde(default)]
+    pub cancel_penalty: Option<CancelPenalty>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {
+    /// Only used when migrating a contract that has no config yet
+    pub owner: Option<String>,
+    pub accepted_denoms: Option<Vec<String>>,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
+    /// Lists an NFT, only buyable by reserved_for when set
     Sell {
         token_id: String,
         contract_address: String,
-        price: Coin,
+        price: Price,
         expiration: Expiration,
+        reserved_for: Option<String>,
+    },
+    /// Lists several NFTs at once. Unless best_effort is set, any failing item fails the batch
+    BatchSell {
+        items: Vec<SellItem>,
+        best_effort: Option<bool>,
+    },
+    BatchDelist {
+        items: Vec<ListingKey>,
+        best_effort: Option<bool>,
+    },
+    /// Buys several listings out of the funds sent along, refunding what is left
+    BatchBuy {
+        items: Vec<ListingKey>,
+        best_effort: Option<bool>,
+    },
+    /// Lists several NFTs of the sender as one lot for a single price
+    SellBundle {
+        nfts: Vec<BundleItem>,
+        price: Price,
+        expiration: Expiration,
+    },
+    /// Auctions several NFTs of the sender as one lot
+    AuctionBundle {
+        nfts: Vec<BundleItem>,
+        starting_price: Price,
+        min_bid_increment: Option<BidIncrement>,
+        expiration: Expiration,
+    },
+    BuyBundle {
+        bundle_id: u64,
+    },
+    BidBundle {
+        bundle_id: u64,
     },
+    /// Settles a finished bundle auction, callable by anyone. The bid is refunded if the
+    /// seller no longer owns or approves every NFT of the bundle
+    ClaimBundle {
+        bundle_id: u64,
+    },
+    /// Withdraws a bundle, auctions only before the first bid
+    DelistBundle {
+        bundle_id: u64,
+    },
+    /// Proposes to swap the offered NFTs, plus extra_funds sent along, for the requested
+    /// NFTs of counterparty
+    ProposeTrade {
+        offered: Vec<BundleItem>,
+        requested: Vec<BundleItem>,
+        extra_funds: Option<Price>,
+        counterparty: String,
+    },
+    /// Swaps the NFTs of a trade, callable by its counterparty
+    AcceptTrade {
+        trade_id: u64,
+    },
+    /// Withdraws a trade and refunds its extra funds, callable by either party
+    CancelTrade {
+        trade_id: u64,
+    },
+    /// Changes the price and expiration of a fixed-price listing
+    UpdatePrice {
+        collection: String,
+        token_id: String,
+        price: Price,
+        expiration: Expiration,
+    },
+    /// Lists an NFT at a price decaying linearly from start_price at start_time to end_price
+    /// at end_time
+    DutchAuction {
+        token_id: String,
+        contract_address: String,
+        start_price: Price,
+        /// In the asset of the starting price
+        end_price: Uint128,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    },
+    /// Pays the listing's current price, refunding any overpayment of a dutch auction
     Buy {
         token_id: String,
+        contract_address: String,
     },
     Delist {
         token_id: String,
@@ -27,32 +135,417 @@ pub enum ExecuteMsg {
     Auction {
         token_id: String,
         contract_address: String,
-        starting_price: Coin,
+        starting_price: Price,
+        min_bid_increment: Option<BidIncrement>,
         expiration: Expiration,
+        reserve_price: Option<ReservePrice>,
+        /// In the asset of the starting price
+        buy_now_price: Option<Uint128>,
     },
     Bid {
         token_id: String,
+        contract_address: String,
     },
+    /// Settles a finished auction, callable by anyone. Without a bid meeting the reserve
+    /// price the NFT stays with the seller. The bid is also refunded if the seller no longer
+    /// owns or approves the NFT
     Claim {
         token_id: String,
+        contract_address: String,
+    },
+    /// Withdraws an auction. Once it has bids this requires the config to allow it, and the
+    /// penalty to be sent along
+    CancelAuction {
+        token_id: String,
+        contract_address: String,
+    },
+    /// Changes an auction that has no bids yet
+    UpdateAuction {
+        token_id: String,
+        contract_address: String,
+        starting_price: Option<Price>,
+        expiration: Option<Expiration>,
+    },
+    UpdateConfig {
+        owner: Option<String>,
+        accepted_denoms: Option<Vec<String>>,
+        accepted_cw20s: Option<Vec<String>>,
+        fee_bps: Option<u64>,
+        fee_collector: Option<String>,
+        /// A window or extension of 0 disables extensions
+        auction_extension: Option<AuctionExtension>,
+        cancel_penalty: Option<CancelPenalty>,
+    },
+    SetRoyalty {
+        contract_address: String,
+        recipient: String,
+        bps: u64,
+    },
+    RemoveRoyalty {
+        contract_address: String,
+    },
+    /// Removes up to limit expired listings, and expired auctions, sealed auctions and
+    /// bundles nobody bid on
+    PruneExpired {
+        limit: Option<u32>,
+    },
+    /// Removes the listings of an NFT whose seller no longer owns it, refunding their bids.
+    /// Callable by anyone
+    ClearStaleListings {
+        contract_address: String,
+        token_id: String,
+    },
+    /// Lists an NFT sent with cw721 SendNft, holding it in escrow until it is sold or delisted
+    ReceiveNft(Cw721ReceiveMsg),
+    /// Buys or bids with cw20 tokens sent through cw20 Send
+    Receive(Cw20ReceiveMsg),
+    /// Offers the native funds sent along for an NFT, listed or not
+    MakeOffer {
+        token_id: String,
+        contract_address: String,
+        expiration: Expiration,
+    },
+    CancelOffer {
+        token_id: String,
+        contract_address: String,
+    },
+    /// Sells the NFT to bidder, callable by its current owner
+    AcceptOffer {
+        token_id: String,
+        contract_address: String,
+        bidder: String,
+    },
+    /// Offers price for each of up to quantity tokens of collection, escrowing
+    /// price * quantity sent along
+    MakeCollectionOffer {
+        collection: String,
+        price: Price,
+        quantity: u64,
+        expiration: Expiration,
+    },
+    CancelCollectionOffer {
+        collection: String,
+    },
+    /// Sells token_id to bidder for one unit of their collection offer
+    AcceptCollectionOffer {
+        collection: String,
+        token_id: String,
+        bidder: String,
+    },
+    /// Auctions an NFT with bids committed until commit_end and revealed until reveal_end
+    SealedAuction {
+        token_id: String,
+        contract_address: String,
+        min_price: Price,
+        commit_end: Timestamp,
+        reveal_end: Timestamp,
+        pricing: SealedBidPricing,
+    },
+    /// Commits to a hidden bid, escrowing the funds sent along as a deposit covering it.
+    /// commitment is the hex encoded sha256 of "{bidder}:{amount}:{salt}"
+    CommitBid {
+        token_id: String,
+        contract_address: String,
+        commitment: String,
+    },
+    RevealBid {
+        token_id: String,
+        contract_address: String,
+        amount: Uint128,
+        salt: String,
+    },
+    /// Sells the NFT to the highest revealed bid once the reveal phase is over, refunding
+    /// the other bids and forfeiting unrevealed deposits to the seller. Every deposit is
+    /// refunded if the seller no longer owns or approves the NFT
+    SettleSealedAuction {
+        token_id: String,
+        contract_address: String,
+    },
+}
+
+/// Hook messages embedded in the cw20 Send call of Receive
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    Buy {
+        token_id: String,
+        contract_address: String,
+    },
+    Bid {
+        token_id: String,
+        contract_address: String,
+    },
+    MakeOffer {
+        token_id: String,
+        contract_address: String,
+        expiration: Expiration,
+    },
+    MakeCollectionOffer {
+        collection: String,
+        price: Price,
+        quantity: u64,
+        expiration: Expiration,
+    },
+    CommitBid {
+        token_id: String,
+        contract_address: String,
+        commitment: String,
+    },
+    BatchBuy {
+        items: Vec<ListingKey>,
+        best_effort: Option<bool>,
+    },
+    BuyBundle {
+        bundle_id: u64,
+    },
+    BidBundle {
+        bundle_id: u64,
+    },
+    /// Proposes a trade with the tokens sent as its extra funds
+    ProposeTrade {
+        offered: Vec<BundleItem>,
+        requested: Vec<BundleItem>,
+        counterparty: String,
+    },
+    /// Pays the penalty for cancelling an auction with bids
+    CancelAuction {
+        token_id: String,
+        contract_address: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SellItem {
+    pub token_id: String,
+    pub contract_address: String,
+    pub price: Price,
+    pub expiration: Expiration,
+    /// The only address allowed to buy the listing
+    pub reserved_for: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListingKey {
+    pub token_id: String,
+    pub contract_address: String,
+}
+
+/// Hook messages embedded in the SendNft call of ReceiveNft
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveNftMsg {
+    Sell {
+        price: Price,
+        expiration: Expiration,
+        reserved_for: Option<String>,
+    },
+    Auction {
+        starting_price: Price,
+        min_bid_increment: Option<BidIncrement>,
+        expiration: Expiration,
+        reserve_price: Option<ReservePrice>,
+        /// In the asset of the starting price
+        buy_now_price: Option<Uint128>,
+    },
+    DutchAuction {
+        start_price: Price,
+        end_price: Uint128,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    },
+    SealedAuction {
+        min_price: Price,
+        commit_end: Timestamp,
+        reveal_end: Timestamp,
+        pricing: SealedBidPricing,
     },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
-    // GetCount returns the current count as a json-encoded number
-    GetListing { token_id: String },
-    GetAuctionListing { token_id: String },
-    GetHighestBidder { token_id: String },
+    Config {},
+    GetRoyalty {
+        token_id: String,
+        contract_address: String,
+        sale_price: Price,
+    },
+    GetListing {
+        token_id: String,
+        contract_address: String,
+    },
+    /// The price a fixed-price or dutch auction listing sells for in the current block
+    GetCurrentPrice {
+        token_id: String,
+        contract_address: String,
+    },
+    /// Fixed-price listings ordered by (contract_address, token_id)
+    GetListings {
+        start_after: Option<(String, String)>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    GetAuctionListing {
+        token_id: String,
+        contract_address: String,
+    },
+    /// Auction listings ordered by (contract_address, token_id)
+    GetAuctionListings {
+        start_after: Option<(String, String)>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    ListingsBySeller {
+        seller: String,
+        start_after: Option<(String, String)>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    /// start_after is a token_id within the collection
+    ListingsByCollection {
+        contract_address: String,
+        start_after: Option<String>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    /// Listings priced in denom between min and max inclusive, cheapest first. Dutch
+    /// auctions are left out as their price keeps changing. start_after is the
+    /// (price, (contract_address, token_id)) of the last listing returned
+    ListingsByPriceRange {
+        denom: String,
+        min: Option<Uint128>,
+        max: Option<Uint128>,
+        start_after: Option<(Uint128, (String, String))>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    AuctionListingsBySeller {
+        seller: String,
+        start_after: Option<(String, String)>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    AuctionListingsByCollection {
+        contract_address: String,
+        start_after: Option<String>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    /// Filters on the starting price of each auction. start_after is the
+    /// (starting_price, (contract_address, token_id)) of the last auction returned
+    AuctionListingsByPriceRange {
+        denom: String,
+        min: Option<Uint128>,
+        max: Option<Uint128>,
+        start_after: Option<(Uint128, (String, String))>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    /// Offers on a single NFT, ordered by bidder
+    OffersByToken {
+        token_id: String,
+        contract_address: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// start_after is the (contract_address, token_id) of the last offer returned
+    OffersByBidder {
+        bidder: String,
+        start_after: Option<(String, String)>,
+        limit: Option<u32>,
+    },
+    GetCollectionOffer {
+        collection: String,
+        bidder: String,
+    },
+    /// Collection offers in denom, best price first. start_after is the (price, bidder) of
+    /// the last offer returned
+    CollectionOffers {
+        collection: String,
+        denom: String,
+        start_after: Option<(Uint128, String)>,
+        include_expired: Option<bool>,
+        limit: Option<u32>,
+    },
+    GetHighestBidder {
+        token_id: String,
+        contract_address: String,
+    },
+    GetTrade {
+        trade_id: u64,
+    },
+    /// Open trades ordered by id
+    GetTrades {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    GetBundle {
+        bundle_id: u64,
+    },
+    /// Bundles ordered by id
+    GetBundles {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    /// Bids accepted in the auction with auction_id, oldest first
+    GetBids {
+        auction_id: u64,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    GetSealedAuction {
+        token_id: String,
+        contract_address: String,
+    },
+    /// Sealed bids on an auction ordered by bidder
+    GetSealedBids {
+        token_id: String,
+        contract_address: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+}
+
+// cw2981 royalty query sent to collections through the cw721 extension query
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw2981QueryMsg {
+    Extension { msg: Cw2981ExtensionMsg },
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw2981ExtensionMsg {
+    RoyaltyInfo {
+        token_id: String,
+        sale_price: Uint128,
+    },
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RoyaltiesInfoResponse {
+    pub address: String,
+    pub royalty_amount: Uint128,
 }
 
 // We define a custom struct for each query response
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub config: Config,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RoyaltyResponse {
+    /// None when the collection pays no royalty
+    pub recipient: Option<String>,
+    pub amount: Price,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ListingResponse {
     pub nft: NFT,
 }
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CurrentPriceResponse {
+    pub price: Price,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ListingsResponse {
     pub nfts: Vec<NFT>,
 }
@@ -61,6 +554,52 @@ pub struct AuctionListingResponse {
     pub auction_nft: AuctionNft,
 }
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AuctionListingsResponse {
+    pub auction_nfts: Vec<AuctionNft>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OffersResponse {
+    pub offers: Vec<Offer>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollectionOfferResponse {
+    pub offer: Option<CollectionOffer>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollectionOffersResponse {
+    pub offers: Vec<CollectionOffer>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+
+pub struct TradeResponse {
+    pub trade: Trade,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TradesResponse {
+    pub trades: Vec<Trade>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BundleResponse {
+    pub bundle: Bundle,
+    pub highest_bid: Option<Bidder>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BundlesResponse {
+    pub bundles: Vec<Bundle>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BidsResponse {
+    pub bids: Vec<BidRecord>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SealedAuctionResponse {
+    pub auction: SealedAuction,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SealedBidsResponse {
+    pub bids: Vec<SealedBid>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 
 pub struct HighestBidderResponse {
     pub bidder: Bidder,
@@ -1,14 +1,158 @@
+use std::fmt;
+
+use cw20::{Cw20Coin, Cw20ExecuteMsg};
 use cw721::Expiration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Coin, Uint128};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{
+    to_binary, Addr, BankMsg, BlockInfo, Coin, CosmosMsg, StdResult, Timestamp, Uint128, WasmMsg,
+};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex, U128Key, U64Key};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct State {
-    pub count: i32,
+pub struct Config {
+    /// Address allowed to update the config
     pub owner: Addr,
+    /// Native denominations listings can be priced in
+    pub accepted_denoms: Vec<String>,
+    /// cw20 token contracts listings can be priced in
+    #[serde(default)]
+    pub accepted_cw20s: Vec<String>,
+    /// Marketplace fee taken from every sale, in basis points
+    pub fee_bps: u64,
+    /// Address receiving the marketplace fee
+    pub fee_collector: Addr,
+    /// Extends auctions on late bids, disabled by default
+    #[serde(default)]
+    pub auction_extension: AuctionExtension,
+    /// Whether sellers may cancel auctions that already received bids
+    #[serde(default)]
+    pub cancel_penalty: CancelPenalty,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelPenalty {
+    /// Auctions can only be cancelled before the first bid
+    Disallowed,
+    /// Cancelling after a bid costs the seller this share of the highest bid, paid to the
+    /// bidder along with their refund
+    BasisPoints(u64),
+}
+
+impl Default for CancelPenalty {
+    fn default() -> Self {
+        CancelPenalty::Disallowed
+    }
+}
+
+/// Anti-sniping window for auctions, counted in the unit of the auction's expiration:
+/// blocks for `AtHeight` and seconds for `AtTime`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct AuctionExtension {
+    /// A bid this close to the end of an auction extends it
+    pub window: u64,
+    /// How far the expiration is pushed forward
+    pub extension: u64,
+}
+
+impl AuctionExtension {
+    /// The extended expiration of an auction receiving a bid in this block, if any. An
+    /// expiration that can't be pushed back any further is left as is
+    pub fn extend(&self, expiration: &Expiration, block: &BlockInfo) -> Option<Expiration> {
+        if self.window == 0 || self.extension == 0 {
+            return None;
+        }
+        match *expiration {
+            Expiration::AtHeight(height) if height.saturating_sub(block.height) <= self.window => {
+                height.checked_add(self.extension).map(Expiration::AtHeight)
+            }
+            Expiration::AtTime(time)
+                if time.seconds().saturating_sub(block.time.seconds()) <= self.window =>
+            {
+                self.extension
+                    .checked_mul(1_000_000_000)
+                    .and_then(|nanos| time.nanos().checked_add(nanos))
+                    .map(|nanos| Expiration::AtTime(Timestamp::from_nanos(nanos)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An amount of either a native coin or a cw20 token
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Price {
+    Native(Coin),
+    Cw20(Cw20Coin),
+}
+
+impl Price {
+    pub fn amount(&self) -> Uint128 {
+        match self {
+            Price::Native(coin) => coin.amount,
+            Price::Cw20(coin) => coin.amount,
+        }
+    }
+
+    /// The native denom, or the address of the cw20 contract
+    pub fn denom(&self) -> &str {
+        match self {
+            Price::Native(coin) => &coin.denom,
+            Price::Cw20(coin) => &coin.address,
+        }
+    }
+
+    /// Whether both prices are paid in the same native denom or cw20 token
+    pub fn same_asset(&self, other: &Price) -> bool {
+        matches!(
+            (self, other),
+            (Price::Native(_), Price::Native(_)) | (Price::Cw20(_), Price::Cw20(_))
+        ) && self.denom() == other.denom()
+    }
+
+    /// The same asset with a different amount
+    pub fn with_amount(&self, amount: Uint128) -> Price {
+        match self {
+            Price::Native(coin) => Price::Native(Coin {
+                denom: coin.denom.clone(),
+                amount,
+            }),
+            Price::Cw20(coin) => Price::Cw20(Cw20Coin {
+                address: coin.address.clone(),
+                amount,
+            }),
+        }
+    }
+
+    /// Message paying this amount out of the contract to recipient
+    pub fn into_msg(self, recipient: String) -> StdResult<CosmosMsg> {
+        Ok(match self {
+            Price::Native(coin) => CosmosMsg::Bank(BankMsg::Send {
+                to_address: recipient,
+                amount: vec![coin],
+            }),
+            Price::Cw20(coin) => CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: coin.address,
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient,
+                    amount: coin.amount,
+                })?,
+                funds: vec![],
+            }),
+        })
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Price::Native(coin) => write!(f, "{}", coin),
+            Price::Cw20(coin) => write!(f, "{}cw20:{}", coin.amount, coin.address),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
@@ -17,8 +161,53 @@ pub struct NFT {
     pub token_id: String,
     pub owner: String,
     pub contract_address: String,
-    pub price: Coin,
+    pub price: Price,
     pub expiration: Expiration,
+    /// Whether the marketplace holds the NFT rather than an approval for it
+    #[serde(default)]
+    pub escrowed: bool,
+    /// Makes `price` the starting price of a descending price sale
+    #[serde(default)]
+    pub dutch_auction: Option<DutchAuction>,
+    /// The only address allowed to buy the listing
+    #[serde(default)]
+    pub reserved_for: Option<String>,
+}
+
+impl NFT {
+    /// The price a buyer pays in this block
+    pub fn current_price(&self, block: &BlockInfo) -> Price {
+        match &self.dutch_auction {
+            Some(dutch_auction) => self
+                .price
+                .with_amount(dutch_auction.price_at(self.price.amount(), block)),
+            None => self.price.clone(),
+        }
+    }
+}
+/// Price of a listing decaying linearly from its starting price to end_price
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct DutchAuction {
+    /// In the asset of the starting price, kept after end_time until the NFT is sold
+    pub end_price: Uint128,
+    pub start_time: Timestamp,
+    pub end_time: Timestamp,
+}
+
+impl DutchAuction {
+    pub fn price_at(&self, start_price: Uint128, block: &BlockInfo) -> Uint128 {
+        let now = block.time.seconds();
+        let (start, end) = (self.start_time.seconds(), self.end_time.seconds());
+        if now <= start {
+            start_price
+        } else if now >= end {
+            self.end_price
+        } else {
+            let decay = (start_price - self.end_price).multiply_ratio(now - start, end - start);
+            start_price - decay
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -26,16 +215,384 @@ pub struct AuctionNft {
     pub token_id: String,
     pub owner: String,
     pub contract_address: String,
-    pub starting_price: Coin,
+    pub starting_price: Price,
+    /// Minimum amount by which a bid has to exceed the current highest bid
+    pub min_bid_increment: Option<BidIncrement>,
     pub expiration: Expiration,
+    /// Whether the marketplace holds the NFT rather than an approval for it
+    #[serde(default)]
+    pub escrowed: bool,
+    /// Lowest winning bid the seller accepts. Hidden reserve prices are reported with an
+    /// amount of 0
+    #[serde(default)]
+    pub reserve_price: Option<ReservePrice>,
+    /// Bid that instantly wins the auction, in the asset of the starting price
+    #[serde(default)]
+    pub buy_now_price: Option<Uint128>,
+    /// Keys the bid history of this auction, apart from other auctions of the token
+    #[serde(default)]
+    pub auction_id: u64,
+}
+
+impl AuctionNft {
+    /// The auction as shown to bidders, without the amount of a hidden reserve price
+    pub fn redact_reserve(mut self) -> Self {
+        if let Some(reserve_price) = self.reserve_price.as_mut() {
+            if reserve_price.hidden {
+                reserve_price.amount = Uint128::zero();
+            }
+        }
+        self
+    }
+
+    /// Whether the highest bid is high enough for the seller to part with the NFT
+    pub fn reserve_met(&self, highest_bid: &Price) -> bool {
+        match &self.reserve_price {
+            Some(reserve_price) => highest_bid.amount() >= reserve_price.amount,
+            None => true,
+        }
+    }
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ReservePrice {
+    /// In the asset of the starting price
+    pub amount: Uint128,
+    /// Whether bidders can see the amount. A hidden reserve may exceed the buy now price,
+    /// which still wins the auction outright
+    pub hidden: bool,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BidIncrement {
+    Absolute(Uint128),
+    /// Fraction of the current highest bid, where 10_000 basis points is 100%
+    BasisPoints(u64),
 }
 #[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
 #[serde(rename_all = "snake_case")]
 pub struct Bidder {
+    pub address: String,
+    pub bid: Price,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct Royalty {
+    pub recipient: Addr,
+    /// Share of the sale price paid to the recipient, in basis points
+    pub bps: u64,
+}
+
+pub const CONFIG: Item<Config> = Item::new("config");
+// Royalties for collections that don't implement cw2981, keyed by contract_address
+pub const ROYALTIES: Map<String, Royalty> = Map::new("royalties");
+
+pub struct ListingIndexes<'a> {
+    pub seller: MultiIndex<'a, (Vec<u8>, Vec<u8>), NFT>,
+    pub collection: MultiIndex<'a, (Vec<u8>, Vec<u8>), NFT>,
+    /// Ordered by amount within each denom
+    pub price: MultiIndex<'a, (Vec<u8>, U128Key, Vec<u8>), NFT>,
+}
+
+impl<'a> IndexList<NFT> for ListingIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<NFT>> + '_> {
+        let v: Vec<&dyn Index<NFT>> = vec![&self.seller, &self.collection, &self.price];
+        Box::new(v.into_iter())
+    }
+}
+
+pub struct AuctionListingIndexes<'a> {
+    pub seller: MultiIndex<'a, (Vec<u8>, Vec<u8>), AuctionNft>,
+    pub collection: MultiIndex<'a, (Vec<u8>, Vec<u8>), AuctionNft>,
+    /// Ordered by starting price within each denom
+    pub price: MultiIndex<'a, (Vec<u8>, U128Key, Vec<u8>), AuctionNft>,
+}
+
+impl<'a> IndexList<AuctionNft> for AuctionListingIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<AuctionNft>> + '_> {
+        let v: Vec<&dyn Index<AuctionNft>> = vec![&self.seller, &self.collection, &self.price];
+        Box::new(v.into_iter())
+    }
+}
+
+// Listings are keyed by (contract_address, token_id) so that tokens sharing an id across
+// different cw721 collections do not collide.
+pub fn listings<'a>() -> IndexedMap<'a, (String, String), NFT, ListingIndexes<'a>> {
+    let indexes = ListingIndexes {
+        seller: MultiIndex::new(
+            |nft, pk| (nft.owner.as_bytes().to_vec(), pk),
+            "listings",
+            "listings__seller",
+        ),
+        collection: MultiIndex::new(
+            |nft, pk| (nft.contract_address.as_bytes().to_vec(), pk),
+            "listings",
+            "listings__collection",
+        ),
+        // dutch auctions are indexed by their start price as their current price changes
+        // every block, so price range queries leave them out
+        price: MultiIndex::new(
+            |nft, pk| {
+                (
+                    nft.price.denom().as_bytes().to_vec(),
+                    U128Key::new(nft.price.amount().u128()),
+                    pk,
+                )
+            },
+            "listings",
+            "listings__price",
+        ),
+    };
+    IndexedMap::new("listings", indexes)
+}
+
+pub fn auction_listings<'a>(
+) -> IndexedMap<'a, (String, String), AuctionNft, AuctionListingIndexes<'a>> {
+    let indexes = AuctionListingIndexes {
+        seller: MultiIndex::new(
+            |nft, pk| (nft.owner.as_bytes().to_vec(), pk),
+            "auction_listings",
+            "auction_listings__seller",
+        ),
+        collection: MultiIndex::new(
+            |nft, pk| (nft.contract_address.as_bytes().to_vec(), pk),
+            "auction_listings",
+            "auction_listings__collection",
+        ),
+        price: MultiIndex::new(
+            |nft, pk| {
+                (
+                    nft.starting_price.denom().as_bytes().to_vec(),
+                    U128Key::new(nft.starting_price.amount().u128()),
+                    pk,
+                )
+            },
+            "auction_listings",
+            "auction_listings__price",
+        ),
+    };
+    IndexedMap::new("auction_listings", indexes)
+}
+
+/// An auction whose bids stay hidden until the reveal phase
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct SealedAuction {
+    pub token_id: String,
+    pub owner: String,
+    pub contract_address: String,
+    /// Lowest winning bid, also setting the asset bids are made in
+    pub min_price: Price,
+    /// Bids are committed until commit_end and revealed until reveal_end
+    pub commit_end: Timestamp,
+    pub reveal_end: Timestamp,
+    pub pricing: SealedBidPricing,
+    /// Whether the marketplace holds the NFT rather than an approval for it
+    pub escrowed: bool,
+    /// Highest bid revealed so far
+    pub highest_bid: Option<Bidder>,
+    /// Second highest amount revealed so far
+    pub second_bid: Option<Uint128>,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SealedBidPricing {
+    /// The winner pays their own bid
+    FirstPrice,
+    /// The winner pays the second highest bid, or the minimum price without one (Vickrey)
+    SecondPrice,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct SealedBid {
+    pub bidder: String,
+    /// Hex encoded sha256 of "{bidder}:{amount}:{salt}"
+    pub commitment: String,
+    /// Escrowed cover for the hidden bid, forfeited to the seller if it is never revealed
+    pub deposit: Price,
+    pub revealed: Option<Uint128>,
+}
+
+pub const SEALED_AUCTIONS: Map<(String, String), SealedAuction> = Map::new("sealed_auctions");
+// Sealed bids keyed by (contract_address, token_id, bidder)
+pub const SEALED_BIDS: Map<(String, String, String), SealedBid> = Map::new("sealed_bids");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct Offer {
+    pub bidder: String,
+    pub contract_address: String,
+    pub token_id: String,
+    /// Escrowed by the marketplace until the offer is accepted or cancelled
+    pub price: Price,
+    pub expiration: Expiration,
+}
+
+pub struct OfferIndexes<'a> {
+    pub bidder: MultiIndex<'a, (Vec<u8>, Vec<u8>), Offer>,
+}
+
+impl<'a> IndexList<Offer> for OfferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Offer>> + '_> {
+        let v: Vec<&dyn Index<Offer>> = vec![&self.bidder];
+        Box::new(v.into_iter())
+    }
+}
+
+// Offers on any NFT, listed or not, keyed by (contract_address, token_id, bidder)
+pub fn offers<'a>() -> IndexedMap<'a, (String, String, String), Offer, OfferIndexes<'a>> {
+    let indexes = OfferIndexes {
+        bidder: MultiIndex::new(
+            |offer, pk| (offer.bidder.as_bytes().to_vec(), pk),
+            "offers",
+            "offers__bidder",
+        ),
+    };
+    IndexedMap::new("offers", indexes)
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct CollectionOffer {
+    pub bidder: String,
+    pub collection: String,
+    /// Paid for each token, price * quantity is escrowed by the marketplace
+    pub price: Price,
+    /// Number of tokens still wanted
+    pub quantity: u64,
+    pub expiration: Expiration,
+}
+
+/// ((collection, denom), amount, pk) of a collection offer
+type CollectionOfferPriceKey = ((Vec<u8>, Vec<u8>), U128Key, Vec<u8>);
+
+pub struct CollectionOfferIndexes<'a> {
+    /// Ordered by amount within each (collection, denom)
+    pub price: MultiIndex<'a, CollectionOfferPriceKey, CollectionOffer>,
+}
+
+impl<'a> IndexList<CollectionOffer> for CollectionOfferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<CollectionOffer>> + '_> {
+        let v: Vec<&dyn Index<CollectionOffer>> = vec![&self.price];
+        Box::new(v.into_iter())
+    }
+}
+
+// Offers on any token of a collection, keyed by (collection, bidder)
+pub fn collection_offers<'a>(
+) -> IndexedMap<'a, (String, String), CollectionOffer, CollectionOfferIndexes<'a>> {
+    let indexes = CollectionOfferIndexes {
+        price: MultiIndex::new(
+            |offer, pk| {
+                (
+                    (
+                        offer.collection.as_bytes().to_vec(),
+                        offer.price.denom().as_bytes().to_vec(),
+                    ),
+                    U128Key::new(offer.price.amount().u128()),
+                    pk,
+                )
+            },
+            "collection_offers",
+            "collection_offers__price",
+        ),
+    };
+    IndexedMap::new("collection_offers", indexes)
+}
+
+pub const HIGHEST_BIDDER: Map<(String, String), Bidder> = Map::new("highest_bidders");
+
+/// Several NFTs of one seller sold or auctioned together as a single lot
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct Bundle {
+    pub id: u64,
+    pub owner: String,
+    pub nfts: Vec<BundleItem>,
+    /// The fixed price, or the starting price of an auction
+    pub price: Price,
+    pub kind: BundleKind,
+    pub expiration: Expiration,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct BundleItem {
+    pub contract_address: String,
+    pub token_id: String,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleKind {
+    FixedPrice,
+    Auction {
+        min_bid_increment: Option<BidIncrement>,
+    },
+}
+
+/// A proposed swap of the proposer's NFTs, plus optional escrowed funds, for NFTs of the
+/// counterparty
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct Trade {
+    pub id: u64,
+    pub proposer: String,
+    pub counterparty: String,
+    pub offered: Vec<BundleItem>,
+    pub requested: Vec<BundleItem>,
+    pub extra_funds: Option<Price>,
+}
+
+pub const TRADES: Map<U64Key, Trade> = Map::new("trades");
+pub const TRADE_COUNT: Item<u64> = Item::new("trade_count");
+
+pub const BUNDLES: Map<U64Key, Bundle> = Map::new("bundles");
+pub const BUNDLE_COUNT: Item<u64> = Item::new("bundle_count");
+pub const BUNDLE_BIDS: Map<U64Key, Bidder> = Map::new("bundle_bids");
+// Bundle id of every NFT listed in a bundle, keyed by (contract_address, token_id)
+pub const BUNDLED_NFTS: Map<(String, String), u64> = Map::new("bundled_nfts");
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct BidRecord {
+    pub id: u64,
+    pub bidder: String,
+    pub bid: Price,
+    pub height: u64,
+    pub time: Timestamp,
+}
+
+pub const AUCTION_COUNT: Item<u64> = Item::new("auction_count");
+// Every accepted bid, keyed by (auction_id, id) with ids counting up per auction
+pub const BIDS: Map<(U64Key, U64Key), BidRecord> = Map::new("bids");
+pub const BID_COUNT: Map<U64Key, u64> = Map::new("bid_count");
+
+// Listings as stored before they were keyed by collection, only read during migration.
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct LegacyNft {
+    pub token_id: String,
+    pub owner: String,
+    pub contract_address: String,
+    pub price: Coin,
+    pub expiration: Expiration,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct LegacyAuctionNft {
+    pub token_id: String,
+    pub owner: String,
+    pub contract_address: String,
+    pub starting_price: Coin,
+    pub expiration: Expiration,
+}
+#[derive(Serialize, Deserialize, Clone, PartialEq, JsonSchema, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct LegacyBidder {
     pub address: String,
     pub bid: Coin,
 }
 
-pub const AUCTION_LIST: Map<String, AuctionNft> = Map::new("AuctionList");
-pub const LIST: Map<String, NFT> = Map::new("List");
-pub const HIGHEST_BIDDER: Map<String, Bidder> = Map::new("Bidders");
+pub const LEGACY_AUCTION_LIST: Map<String, LegacyAuctionNft> = Map::new("AuctionList");
+pub const LEGACY_LIST: Map<String, LegacyNft> = Map::new("List");
+pub const LEGACY_HIGHEST_BIDDER: Map<String, LegacyBidder> = Map::new("Bidders");